@@ -0,0 +1,147 @@
+use clap::{Args, ValueEnum};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    LlamaCpp,
+    RwkvCpp,
+    LlmRs,
+}
+
+// The architectures llm-rs can load, the other backends only run their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ModelType {
+    Llama,
+    Bloom,
+    Gpt2,
+    #[value(name = "gptj")]
+    GptJ,
+    #[value(name = "gptneox")]
+    GptNeoX,
+    Mpt,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ModelArgs {
+    /// Backend that runs the model
+    #[arg(short, long, value_enum, default_value_t = Backend::LlamaCpp)]
+    pub backend: Backend,
+
+    /// Path of the model file
+    #[arg(short, long)]
+    pub model: String,
+
+    /// Architecture of the model, only used by llm-rs
+    #[arg(long, value_enum, default_value_t = ModelType::Llama)]
+    pub model_type: ModelType,
+
+    /// Size of the context in tokens, not used by rwkv-cpp
+    #[arg(long, default_value_t = 2048)]
+    pub n_ctx: u32,
+
+    /// Number of threads
+    #[arg(short, long, default_value_t = num_cpus::get_physical() as u32)]
+    pub threads: u32,
+
+    /// Number of layers offloaded to the GPU, not used by llm-rs
+    #[arg(long, default_value_t = 0)]
+    pub n_gpu_layers: u32,
+
+    /// Path of a LoRA adapter applied on load, not used by rwkv-cpp
+    #[arg(long)]
+    pub lora: Option<String>,
+
+    /// Path of an f16 model the LoRA adapter is applied to, only used by llama-cpp
+    #[arg(long, requires = "lora")]
+    pub lora_base: Option<String>,
+
+    /// Path of the tokenizer json, only used by rwkv-cpp which embeds the 20B tokenizer otherwise
+    #[arg(long)]
+    pub tokenizer: Option<String>,
+
+    /// Read the model into memory instead of mapping the file, not used by rwkv-cpp
+    #[arg(long)]
+    pub no_mmap: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct GenerateArgs {
+    /// Maximum number of tokens to generate
+    #[arg(short = 'n', long, default_value_t = 256)]
+    pub max_tokens: u32,
+
+    /// Higher values make the output more random
+    #[arg(long, default_value_t = 0.8)]
+    pub temperature: f64,
+
+    /// Only sample from the most likely tokens whose probabilities add up to this
+    #[arg(long, default_value_t = 0.95)]
+    pub top_p: f64,
+
+    /// Not used by rwkv-cpp
+    #[arg(long)]
+    pub top_k: Option<u32>,
+
+    /// Not used by rwkv-cpp
+    #[arg(long)]
+    pub repeat_penalty: Option<f64>,
+
+    /// Seed of the sampling, random when missing
+    #[arg(long)]
+    pub seed: Option<i32>,
+
+    /// Stop the generation once this text is generated, can be repeated. llm-rs does not
+    /// support it.
+    #[arg(long)]
+    pub stop: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum QuantizeFormat {
+    /// Not supported by rwkv-cpp
+    F16,
+    #[value(name = "q4_0")]
+    Q4_0,
+    #[value(name = "q4_1")]
+    Q4_1,
+    #[value(name = "q5_0")]
+    Q5_0,
+    #[value(name = "q5_1")]
+    Q5_1,
+    #[value(name = "q8_0")]
+    Q8_0,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct QuantizeArgs {
+    /// Backend whose file format is quantized, llm-rs is not supported
+    #[arg(short, long, value_enum, default_value_t = Backend::LlamaCpp)]
+    pub backend: Backend,
+
+    /// Path of the f16 or f32 model
+    pub input: String,
+
+    /// Path of the quantized model
+    pub output: String,
+
+    /// Tensor type of the quantized model
+    #[arg(long = "type", value_enum)]
+    pub format: QuantizeFormat,
+
+    /// Number of threads, only used by llama-cpp
+    #[arg(short, long, default_value_t = num_cpus::get_physical() as u32)]
+    pub threads: u32,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct InspectArgs {
+    /// Backend whose file format is read
+    #[arg(short, long, value_enum, default_value_t = Backend::LlamaCpp)]
+    pub backend: Backend,
+
+    /// Architecture of the model, only used by llm-rs
+    #[arg(long, value_enum, default_value_t = ModelType::Llama)]
+    pub model_type: ModelType,
+
+    /// Path of the model file
+    pub model: String,
+}
@@ -0,0 +1,179 @@
+use std::{fs::File, io::Read};
+
+use anyhow::{bail, Context, Result};
+use rwkv_node_cpp::RWKVModelHeader;
+
+use crate::args::{Backend, InspectArgs, ModelType};
+
+// Magic numbers of the ggml file formats, only the unversioned `ggml` has no version field.
+const GGML_MAGIC: u32 = 0x67676d6c;
+const GGMF_MAGIC: u32 = 0x67676d66;
+const GGJT_MAGIC: u32 = 0x67676a74;
+
+// ggml file types, newer files add the quantization version times 1000 to it.
+const FILE_TYPES: [&str; 10] = [
+    "F32",
+    "F16",
+    "Q4_0",
+    "Q4_1",
+    "Q4_1_SOME_F16",
+    "Q4_2",
+    "Q4_3",
+    "Q8_0",
+    "Q5_0",
+    "Q5_1",
+];
+const QUANTIZATION_VERSION_FACTOR: i32 = 1000;
+
+#[derive(Clone, Copy)]
+enum Field {
+    I32(&'static str),
+    F32(&'static str),
+    FileType,
+}
+
+// The hyperparameters that follow the magic, in the order llama.cpp and llm-rs write them.
+fn hyperparameters(model_type: ModelType) -> &'static [Field] {
+    use Field::*;
+
+    match model_type {
+        ModelType::Llama => &[
+            I32("n_vocab"),
+            I32("n_embd"),
+            I32("n_mult"),
+            I32("n_head"),
+            I32("n_layer"),
+            I32("n_rot"),
+            FileType,
+        ],
+        ModelType::Bloom => &[
+            I32("n_vocab"),
+            I32("n_embd"),
+            I32("n_mult"),
+            I32("n_head"),
+            I32("n_layer"),
+            FileType,
+        ],
+        ModelType::Gpt2 => &[
+            I32("n_vocab"),
+            I32("n_ctx"),
+            I32("n_embd"),
+            I32("n_head"),
+            I32("n_layer"),
+            FileType,
+        ],
+        ModelType::GptJ => &[
+            I32("n_vocab"),
+            I32("n_ctx"),
+            I32("n_embd"),
+            I32("n_head"),
+            I32("n_layer"),
+            I32("n_rot"),
+            FileType,
+        ],
+        ModelType::GptNeoX => &[
+            I32("n_vocab"),
+            I32("n_ctx"),
+            I32("n_embd"),
+            I32("n_head"),
+            I32("n_layer"),
+            I32("n_rot"),
+            I32("use_parallel_residual"),
+            FileType,
+        ],
+        ModelType::Mpt => &[
+            I32("n_embd"),
+            I32("max_seq_len"),
+            I32("n_head"),
+            I32("n_layer"),
+            I32("n_vocab"),
+            F32("alibi_bias_max"),
+            F32("clip_qkv"),
+            FileType,
+        ],
+    }
+}
+
+fn read_u32(file: &mut File) -> Result<u32> {
+    let mut buffer = [0u8; 4];
+    file.read_exact(&mut buffer)
+        .context("The file ended inside the header")?;
+    Ok(u32::from_le_bytes(buffer))
+}
+
+fn file_type_name(file_type: i32) -> String {
+    let quantization_version = file_type / QUANTIZATION_VERSION_FACTOR;
+    let name = FILE_TYPES
+        .get((file_type % QUANTIZATION_VERSION_FACTOR) as usize)
+        .map_or_else(
+            || format!("unknown ({})", file_type),
+            |name| name.to_string(),
+        );
+
+    if quantization_version > 0 {
+        format!("{} (quantization v{})", name, quantization_version)
+    } else {
+        name
+    }
+}
+
+fn print_field(name: &str, value: impl std::fmt::Display) {
+    println!("{:<24}{}", name, value);
+}
+
+// Prints the header of a model file, the rest of the file is not read.
+pub fn inspect(args: &InspectArgs) -> Result<()> {
+    let mut file =
+        File::open(&args.model).with_context(|| format!("Cannot open {}", args.model))?;
+    let size = file.metadata()?.len();
+    print_field(
+        "file size",
+        format!("{:.2} MiB", size as f64 / 1024.0 / 1024.0),
+    );
+
+    if args.backend == Backend::RwkvCpp {
+        let header = match RWKVModelHeader::read_from_file(&args.model) {
+            Some(header) => header,
+            None => bail!("{} is not an rwkv.cpp model", args.model),
+        };
+        print_field("format", format!("rwkv.cpp v{}", header.version));
+        print_field("n_vocab", header.n_vocab);
+        print_field("n_embed", header.n_embed);
+        print_field("n_layer", header.n_layer);
+        print_field(
+            "data_type",
+            header
+                .data_type_name()
+                .map_or_else(|| format!("unknown ({})", header.data_type), String::from),
+        );
+        return Ok(());
+    }
+
+    let format = match read_u32(&mut file)? {
+        GGML_MAGIC => "ggml".to_string(),
+        GGMF_MAGIC => format!("ggmf v{}", read_u32(&mut file)?),
+        GGJT_MAGIC => format!("ggjt v{}", read_u32(&mut file)?),
+        magic => bail!(
+            "{} is not a ggml model, its magic is {:#x}",
+            args.model,
+            magic
+        ),
+    };
+    print_field("format", format);
+
+    // llama.cpp only runs LLaMA models.
+    let model_type = match args.backend {
+        Backend::LlmRs => args.model_type,
+        _ => ModelType::Llama,
+    };
+    for field in hyperparameters(model_type) {
+        let value = read_u32(&mut file)?;
+        match *field {
+            Field::I32(name) => print_field(name, value as i32),
+            Field::F32(name) => print_field(name, f32::from_bits(value)),
+            Field::FileType => print_field("file_type", file_type_name(value as i32)),
+        }
+    }
+
+    Ok(())
+}
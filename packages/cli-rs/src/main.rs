@@ -0,0 +1,239 @@
+#![deny(clippy::all)]
+
+mod args;
+mod inspect;
+mod model;
+
+use std::{
+    io::{self, Read, Write},
+    sync::Arc,
+    time::Instant,
+};
+
+use anyhow::{bail, Result};
+use clap::{Parser, Subcommand};
+use common_rs::{abort::AbortFlag, logger::LLamaLogger};
+use llama_node_cpp::{llama::LLamaInternal, types::QuantizeType};
+use rustyline::{error::ReadlineError, DefaultEditor};
+use rwkv_node_cpp::rwkv::RWKVInternal;
+
+use args::{Backend, GenerateArgs, InspectArgs, ModelArgs, QuantizeArgs, QuantizeFormat};
+use model::{backend_error, Model, Task};
+
+/// Run llama.cpp, rwkv.cpp and llm-rs models without Node
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// Print the logs of the backends
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate the completion of a prompt
+    Infer {
+        #[command(flatten)]
+        model: ModelArgs,
+        #[command(flatten)]
+        generate: GenerateArgs,
+        /// The prompt, read from stdin when missing
+        prompt: Option<String>,
+    },
+    /// Prompt the model interactively, every line is a new generation
+    Repl {
+        #[command(flatten)]
+        model: ModelArgs,
+        #[command(flatten)]
+        generate: GenerateArgs,
+        /// Wraps every line, `{prompt}` is replaced by the line
+        #[arg(long, default_value = "{prompt}")]
+        template: String,
+    },
+    /// Print the embedding of every text as a JSON array, one per line
+    Embed {
+        #[command(flatten)]
+        model: ModelArgs,
+        /// L2 normalize the embeddings
+        #[arg(long)]
+        normalize: bool,
+        /// The texts, every line of stdin when missing
+        texts: Vec<String>,
+    },
+    /// Print the token ids of a text as a JSON array
+    Tokenize {
+        #[command(flatten)]
+        model: ModelArgs,
+        /// The text, read from stdin when missing
+        text: Option<String>,
+    },
+    /// Quantize a llama.cpp or rwkv.cpp model file
+    Quantize(QuantizeArgs),
+    /// Print the header of a model file
+    Inspect(InspectArgs),
+}
+
+fn read_stdin() -> Result<String> {
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input)?;
+    Ok(input)
+}
+
+// Streams the generation to stdout, Ctrl-C stops it at the next token.
+async fn generate(model: &Arc<Model>, prompt: String, args: &GenerateArgs) -> Result<()> {
+    let abort = AbortFlag::default();
+    let start = Instant::now();
+    let mut task = {
+        let (model, args, abort) = (model.clone(), args.clone(), abort.clone());
+        tokio::task::spawn_blocking(move || {
+            model.generate(&prompt, &args, &abort, |token| {
+                print!("{}", token);
+                io::stdout().flush().ok();
+            })
+        })
+    };
+
+    let summary = tokio::select! {
+        summary = &mut task => summary?,
+        _ = tokio::signal::ctrl_c() => {
+            abort.abort();
+            task.await?
+        }
+    }?;
+
+    let seconds = start.elapsed().as_secs_f64();
+    eprintln!(
+        "\n\n[{}: {} prompt tokens, {} generated tokens in {:.2}s, {:.2} tokens/s]",
+        summary.finish_reason.as_deref().unwrap_or("Unknown"),
+        summary.prompt_tokens,
+        summary.generated_tokens,
+        seconds,
+        summary.generated_tokens as f64 / seconds
+    );
+    Ok(())
+}
+
+async fn repl(model: Arc<Model>, args: &GenerateArgs, template: &str) -> Result<()> {
+    let mut editor = DefaultEditor::new()?;
+    eprintln!("Enter a prompt, Ctrl-C stops the generation and Ctrl-D exits.");
+
+    loop {
+        let line = match editor.readline("> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line.as_str())?;
+
+        // A failed generation does not end the session.
+        if let Err(e) = generate(&model, template.replace("{prompt}", &line), args).await {
+            eprintln!("\nError: {}", e);
+        }
+    }
+}
+
+fn quantize(args: &QuantizeArgs) -> Result<()> {
+    let start = Instant::now();
+
+    match args.backend {
+        Backend::LlamaCpp => {
+            let quantize_type = match args.format {
+                QuantizeFormat::F16 => QuantizeType::F16,
+                QuantizeFormat::Q4_0 => QuantizeType::Q4_0,
+                QuantizeFormat::Q4_1 => QuantizeType::Q4_1,
+                QuantizeFormat::Q5_0 => QuantizeType::Q5_0,
+                QuantizeFormat::Q5_1 => QuantizeType::Q5_1,
+                QuantizeFormat::Q8_0 => QuantizeType::Q8_0,
+            };
+            LLamaInternal::quantize(
+                &args.input,
+                &args.output,
+                quantize_type,
+                args.threads as i32,
+            )
+        }
+        Backend::RwkvCpp => {
+            let format = match args.format {
+                QuantizeFormat::F16 => bail!("rwkv.cpp cannot quantize to f16"),
+                QuantizeFormat::Q4_0 => "Q4_0",
+                QuantizeFormat::Q4_1 => "Q4_1",
+                QuantizeFormat::Q5_0 => "Q5_0",
+                QuantizeFormat::Q5_1 => "Q5_1",
+                QuantizeFormat::Q8_0 => "Q8_0",
+            };
+            RWKVInternal::quantize(&args.input, &args.output, format)
+        }
+        Backend::LlmRs => bail!("Only llama-cpp and rwkv-cpp models can be quantized"),
+    }
+    .map_err(backend_error)?;
+
+    eprintln!(
+        "Quantized {} into {} in {:.2}s",
+        args.input,
+        args.output,
+        start.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let logger = LLamaLogger::get_singleton();
+    logger.set_enabled(args.verbose);
+
+    match args.command {
+        Command::Infer {
+            model,
+            generate: generate_args,
+            prompt,
+        } => {
+            let prompt = match prompt {
+                Some(prompt) => prompt,
+                None => read_stdin()?,
+            };
+            let model = Arc::new(Model::load(&model, Task::Generate, args.verbose).await?);
+            generate(&model, prompt, &generate_args).await?;
+        }
+        Command::Repl {
+            model,
+            generate: generate_args,
+            template,
+        } => {
+            let model = Arc::new(Model::load(&model, Task::Generate, args.verbose).await?);
+            repl(model, &generate_args, &template).await?;
+        }
+        Command::Embed {
+            model,
+            normalize,
+            mut texts,
+        } => {
+            if texts.is_empty() {
+                texts = read_stdin()?.lines().map(String::from).collect();
+            }
+            let model = Model::load(&model, Task::Embed, args.verbose).await?;
+            for embedding in model.embed(&texts, normalize, &AbortFlag::default())? {
+                println!("{}", serde_json::to_string(&embedding)?);
+            }
+        }
+        Command::Tokenize { model, text } => {
+            let text = match text {
+                Some(text) => text,
+                None => read_stdin()?,
+            };
+            let model = Model::load(&model, Task::Tokenize, args.verbose).await?;
+            println!("{}", serde_json::to_string(&model.tokenize(&text).await?)?);
+        }
+        Command::Quantize(quantize_args) => quantize(&quantize_args)?,
+        Command::Inspect(inspect_args) => inspect::inspect(&inspect_args)?,
+    }
+
+    Ok(())
+}
@@ -0,0 +1,304 @@
+use std::cell::RefCell;
+
+use anyhow::{anyhow, bail, Result};
+use common_rs::abort::AbortFlag;
+use llama_node::{context::LLMContext, types as llm_types};
+use llama_node_cpp::{llama::LLamaInternal, types as llama_types};
+use rwkv_node_cpp::{rwkv::RWKVInternal, types as rwkv_types, RWKVInvocation, TokenizerSource};
+
+use crate::args::{Backend, GenerateArgs, ModelArgs, ModelType};
+
+// What the model is loaded for, llama.cpp only loads what the task needs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Task {
+    Generate,
+    Embed,
+    Tokenize,
+}
+
+enum LoadedModel {
+    LLama(LLamaInternal),
+    // The rwkv.cpp context keeps its tokenizer inline.
+    Rwkv(Box<RWKVInternal>),
+    Llm(LLMContext),
+}
+
+pub struct Model {
+    n_threads: u32,
+    model: LoadedModel,
+}
+
+// How a generation ended, with the token counts reported by the backend.
+pub struct Summary {
+    pub finish_reason: Option<String>,
+    pub prompt_tokens: u32,
+    pub generated_tokens: u32,
+}
+
+// Backend independent inference events.
+enum Event {
+    Token(String),
+    Error(String),
+    End(Summary),
+}
+
+impl From<ModelType> for llm_types::ModelType {
+    fn from(model_type: ModelType) -> Self {
+        match model_type {
+            ModelType::Llama => llm_types::ModelType::Llama,
+            ModelType::Bloom => llm_types::ModelType::Bloom,
+            ModelType::Gpt2 => llm_types::ModelType::Gpt2,
+            ModelType::GptJ => llm_types::ModelType::GptJ,
+            ModelType::GptNeoX => llm_types::ModelType::GptNeoX,
+            ModelType::Mpt => llm_types::ModelType::Mpt,
+        }
+    }
+}
+
+// The backends return napi errors, their reason starts with the `[Code]` of the error.
+pub fn backend_error(error: napi::Error) -> anyhow::Error {
+    anyhow!(error.reason)
+}
+
+impl Model {
+    pub async fn load(args: &ModelArgs, task: Task, enable_logger: bool) -> Result<Self> {
+        let abort = AbortFlag::default();
+
+        let model = match args.backend {
+            Backend::LlamaCpp => {
+                let params = llama_types::ModelLoad {
+                    model_path: args.model.clone(),
+                    n_ctx: args.n_ctx as i32,
+                    n_gpu_layers: args.n_gpu_layers as i32,
+                    vocab_only: task == Task::Tokenize,
+                    embedding: task == Task::Embed,
+                    use_mmap: !args.no_mmap,
+                    lora: args
+                        .lora
+                        .clone()
+                        .map(|lora_adapter| llama_types::LlamaLoraAdaptor {
+                            lora_adapter,
+                            lora_base: args.lora_base.clone(),
+                            n_threads: args.threads as i32,
+                        }),
+                    ..Default::default()
+                };
+                let llama = LLamaInternal::load(params, enable_logger, &abort)
+                    .await
+                    .map_err(backend_error)?;
+                LoadedModel::LLama(llama)
+            }
+            Backend::RwkvCpp => {
+                let params = rwkv_types::ModelLoad {
+                    model_path: args.model.clone(),
+                    tokenizer_path: args.tokenizer.clone(),
+                    n_threads: args.threads,
+                    n_gpu_layers: args.n_gpu_layers,
+                    ..Default::default()
+                };
+                let tokenizer = TokenizerSource::from_params(&params).map_err(backend_error)?;
+                let rwkv = RWKVInternal::load(&params, tokenizer, enable_logger, &abort)
+                    .await
+                    .map_err(backend_error)?;
+                LoadedModel::Rwkv(Box::new(rwkv))
+            }
+            Backend::LlmRs => {
+                let params = llm_types::ModelLoad {
+                    model_type: args.model_type.into(),
+                    model_path: args.model.clone(),
+                    num_ctx_tokens: Some(args.n_ctx as i64),
+                    use_mmap: Some(!args.no_mmap),
+                    lora_path: args.lora.clone(),
+                };
+                let llm = LLMContext::load_model(&params, &abort)
+                    .await
+                    .map_err(backend_error)?;
+                LoadedModel::Llm(llm)
+            }
+        };
+
+        Ok(Self {
+            n_threads: args.threads,
+            model,
+        })
+    }
+
+    // Generates a completion of `prompt`, `on_token` gets every piece of text as it is sampled.
+    pub fn generate(
+        &self,
+        prompt: &str,
+        args: &GenerateArgs,
+        abort: &AbortFlag,
+        on_token: impl Fn(&str),
+    ) -> Result<Summary> {
+        let error = RefCell::new(None);
+        let summary = RefCell::new(None);
+        let handle = |event: Event| match event {
+            Event::Token(token) => on_token(&token),
+            Event::Error(message) => *error.borrow_mut() = Some(message),
+            Event::End(end) => *summary.borrow_mut() = Some(end),
+        };
+
+        let result = match &self.model {
+            LoadedModel::LLama(llama) => {
+                let params = llama_types::Generate {
+                    n_threads: self.n_threads as i32,
+                    n_tok_predict: args.max_tokens as i32,
+                    temp: Some(args.temperature),
+                    top_p: Some(args.top_p),
+                    top_k: args.top_k.map(|top_k| top_k as i32),
+                    repeat_penalty: args.repeat_penalty,
+                    seed: args.seed,
+                    stop_sequences: Some(args.stop.clone()),
+                    prompt: prompt.to_string(),
+                    ..Default::default()
+                };
+                llama.inference(&params, abort, |result| {
+                    if let Some(event) = from_llama_result(result) {
+                        handle(event);
+                    }
+                })
+            }
+            LoadedModel::Rwkv(rwkv) => {
+                let params = RWKVInvocation {
+                    max_predict_length: args.max_tokens as i32,
+                    temp: args.temperature,
+                    top_p: args.top_p,
+                    seed: args.seed,
+                    stop_sequences: Some(args.stop.clone()),
+                    prompt: prompt.to_string(),
+                    ..Default::default()
+                };
+                rwkv.inference(&params, abort, |result| handle(from_rwkv_result(result)))
+            }
+            LoadedModel::Llm(llm) => {
+                if !args.stop.is_empty() {
+                    bail!("llm-rs does not support stop sequences");
+                }
+                let defaults = llm_types::Generate::default();
+                let params = llm_types::Generate {
+                    num_threads: self.n_threads as i32,
+                    num_predict: args.max_tokens as i64,
+                    temperature: args.temperature,
+                    top_p: args.top_p,
+                    top_k: args.top_k.map_or(defaults.top_k, |top_k| top_k as i64),
+                    repeat_penalty: args.repeat_penalty.unwrap_or(defaults.repeat_penalty),
+                    seed: args.seed.map(|seed| seed as i64),
+                    prompt: prompt.to_string(),
+                    ..defaults
+                };
+                llm.inference(&params, abort, |result| handle(from_llm_result(result)))
+            }
+        };
+
+        result.map_err(backend_error)?;
+        if let Some(message) = error.into_inner() {
+            bail!(message);
+        }
+        summary
+            .into_inner()
+            .ok_or_else(|| anyhow!("The generation ended without a result"))
+    }
+
+    // Embeddings of `texts`, in the same order.
+    pub fn embed(
+        &self,
+        texts: &[String],
+        normalize: bool,
+        abort: &AbortFlag,
+    ) -> Result<Vec<Vec<f64>>> {
+        let embeddings = match &self.model {
+            LoadedModel::LLama(llama) => {
+                let params = llama_types::Generate {
+                    n_threads: self.n_threads as i32,
+                    normalize_embedding: Some(normalize),
+                    ..Default::default()
+                };
+                llama.embeddings(texts, &params, abort, |_| {})
+            }
+            LoadedModel::Rwkv(rwkv) => rwkv.embeddings(texts, normalize, abort, |_| {}),
+            LoadedModel::Llm(llm) => {
+                let params = llm_types::Generate {
+                    num_threads: self.n_threads as i32,
+                    normalize_embedding: normalize,
+                    ..Default::default()
+                };
+                llm.get_embeddings(texts, &params, abort, |_| {})
+            }
+        };
+
+        embeddings.map_err(backend_error)
+    }
+
+    pub async fn tokenize(&self, text: &str) -> Result<Vec<i32>> {
+        let tokens = match &self.model {
+            LoadedModel::LLama(llama) => llama.tokenize(text).await,
+            LoadedModel::Rwkv(rwkv) => rwkv.tokenize(text).await,
+            LoadedModel::Llm(llm) => llm.tokenize(text).await,
+        };
+
+        tokens.map_err(backend_error)
+    }
+}
+
+fn from_llama_result(result: llama_types::InferenceResult) -> Option<Event> {
+    use llama_types::InferenceResultType;
+
+    match result.r#type {
+        InferenceResultType::Data => result.data.map(|data| Event::Token(data.token)),
+        InferenceResultType::Error => Some(Event::Error(result.message.unwrap_or_default())),
+        InferenceResultType::Progress => None,
+        InferenceResultType::End => {
+            let usage = result.usage.unwrap_or_default();
+            Some(Event::End(Summary {
+                finish_reason: result.finish_reason.map(|reason| format!("{:?}", reason)),
+                prompt_tokens: usage.n_prompt_tokens as u32,
+                generated_tokens: usage.n_generated_tokens as u32,
+            }))
+        }
+    }
+}
+
+fn from_rwkv_result(result: rwkv_types::InferenceResult) -> Event {
+    use rwkv_types::InferenceResultType;
+
+    match result.r#type {
+        InferenceResultType::Data => {
+            Event::Token(result.data.map(|data| data.token).unwrap_or_default())
+        }
+        InferenceResultType::Error => Event::Error(result.message.unwrap_or_default()),
+        InferenceResultType::End => Event::End(Summary {
+            finish_reason: result.finish_reason.map(|reason| format!("{:?}", reason)),
+            prompt_tokens: result
+                .usage
+                .as_ref()
+                .map_or(0, |usage| usage.n_prompt_tokens),
+            generated_tokens: result
+                .usage
+                .as_ref()
+                .map_or(0, |usage| usage.n_generated_tokens),
+        }),
+    }
+}
+
+fn from_llm_result(result: llm_types::InferenceResult) -> Event {
+    use llm_types::InferenceResultType;
+
+    match result.r#type {
+        InferenceResultType::Data => {
+            Event::Token(result.data.map(|data| data.token).unwrap_or_default())
+        }
+        InferenceResultType::Error => Event::Error(result.message.unwrap_or_default()),
+        InferenceResultType::End => Event::End(Summary {
+            finish_reason: result.finish_reason.map(|reason| format!("{:?}", reason)),
+            prompt_tokens: result
+                .usage
+                .as_ref()
+                .map_or(0, |usage| usage.n_prompt_tokens),
+            generated_tokens: result
+                .usage
+                .as_ref()
+                .map_or(0, |usage| usage.n_generated_tokens),
+        }),
+    }
+}
@@ -0,0 +1,150 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use napi::{
+    threadsafe_function::{
+        ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction, ThreadsafeFunctionCallMode,
+    },
+    Env, JsFunction, JsObject, JsUnknown,
+};
+use tokio::sync::Notify;
+
+use crate::error::ErrorCode;
+
+#[derive(Default)]
+struct AbortState {
+    aborted: AtomicBool,
+    notify: Notify,
+}
+
+/// Cancellation flag shared between the JS thread and the thread running a request.
+#[derive(Clone, Default)]
+pub struct AbortFlag {
+    state: Arc<AbortState>,
+    // Keeps the listener on the `AbortSignal` registered until every clone is dropped.
+    listener: Option<Arc<SignalListener>>,
+}
+
+impl AbortFlag {
+    /// Creates a flag that is raised when the given `AbortSignal` aborts. The listener added to
+    /// the signal is removed once the flag and all its clones are dropped, so the clones
+    /// should not outlive the task, use `detached` for the handles returned to JS.
+    pub fn from_signal(env: &Env, signal: Option<JsObject>) -> napi::Result<Self> {
+        let mut flag = Self::default();
+
+        if let Some(signal) = signal {
+            if signal.get_named_property::<bool>("aborted")? {
+                flag.abort();
+            } else {
+                // Holds the state only, the flag would keep its own listener alive.
+                let state = flag.state.clone();
+                let listener = env
+                    .create_function_from_closure("onabort", move |_| {
+                        state.abort();
+                        Ok(())
+                    })?
+                    .into_unknown();
+                let mut options = env.create_object()?;
+                options.set_named_property("once", env.get_boolean(true)?)?;
+
+                let add_event_listener: JsFunction =
+                    signal.get_named_property("addEventListener")?;
+                add_event_listener.call(
+                    Some(&signal),
+                    &[
+                        &env.create_string("abort")?.into_unknown(),
+                        &listener,
+                        &options.into_unknown(),
+                    ],
+                )?;
+
+                flag.listener = Some(Arc::new(SignalListener::new(env, signal, &listener)?));
+            }
+        }
+
+        Ok(flag)
+    }
+
+    /// A flag sharing the same state that does not keep the signal listener registered.
+    pub fn detached(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+            listener: None,
+        }
+    }
+
+    pub fn abort(&self) {
+        self.state.abort();
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.state.aborted.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once the flag is raised.
+    pub async fn aborted(&self) {
+        loop {
+            // Created before the check, so an abort in between still wakes it.
+            let notified = self.state.notify.notified();
+            if self.is_aborted() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Fails with the aborted error once the flag is raised.
+    pub fn check(&self) -> napi::Result<()> {
+        if self.is_aborted() {
+            Err(aborted_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl AbortState {
+    fn abort(&self) {
+        self.aborted.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+}
+
+// Removes the listener from the signal when dropped, from whichever thread drops it.
+struct SignalListener(ThreadsafeFunction<(), ErrorStrategy::Fatal>);
+
+impl SignalListener {
+    fn new(env: &Env, signal: JsObject, listener: &JsUnknown) -> napi::Result<Self> {
+        let remove_event_listener: JsObject = signal.get_named_property("removeEventListener")?;
+        let bind: JsFunction = remove_event_listener.get_named_property("bind")?;
+        let remove: JsUnknown = bind.call(
+            Some(&remove_event_listener),
+            &[
+                &signal.into_unknown(),
+                &env.create_string("abort")?.into_unknown(),
+                listener,
+            ],
+        )?;
+        let remove = JsFunction::try_from(remove)?;
+
+        let mut tsfn = remove
+            .create_threadsafe_function(0, |_: ThreadSafeCallContext<()>| Ok(Vec::<()>::new()))?;
+        // The pending removal should not keep the process alive.
+        tsfn.unref(env)?;
+
+        Ok(Self(tsfn))
+    }
+}
+
+impl Drop for SignalListener {
+    fn drop(&mut self) {
+        self.0.call((), ThreadsafeFunctionCallMode::NonBlocking);
+    }
+}
+
+/// The error aborted calls reject with, its `code` is `Cancelled` on the JS side.
+pub fn aborted_error() -> napi::Error {
+    ErrorCode::Aborted.error("The operation was aborted")
+}
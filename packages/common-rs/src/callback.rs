@@ -0,0 +1,22 @@
+use napi::{
+    bindgen_prelude::ToNapiValue,
+    threadsafe_function::{
+        ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction, ThreadsafeFunctionCallMode,
+    },
+    JsFunction,
+};
+
+/// A JS callback the threads running the models call with a single value, without waiting for it.
+pub struct Callback<T: 'static>(ThreadsafeFunction<T, ErrorStrategy::Fatal>);
+
+impl<T: ToNapiValue + 'static> Callback<T> {
+    pub fn new(callback: &JsFunction) -> napi::Result<Self> {
+        callback
+            .create_threadsafe_function(0, |ctx: ThreadSafeCallContext<T>| Ok(vec![ctx.value]))
+            .map(Self)
+    }
+
+    pub fn call(&self, value: T) {
+        self.0.call(value, ThreadsafeFunctionCallMode::NonBlocking);
+    }
+}
@@ -0,0 +1,77 @@
+use std::fmt::Display;
+
+use napi::Status;
+use serde::de::DeserializeOwned;
+
+/// Error codes shared by every backend. The code is sent to JS as a `[Code] ` prefix of the
+/// message, the TS wrappers turn it back into the matching `LLMNativeError` subclass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// A parameter is missing, has the wrong type or an invalid value.
+    InvalidParam,
+    /// The model, its tokenizer or an adapter could not be loaded.
+    ModelLoadFailed,
+    /// The input does not fit in the context window.
+    ContextFull,
+    /// The call was aborted through its `AbortSignal`.
+    Aborted,
+    /// The model was disposed before or while the call ran.
+    Disposed,
+    /// The backend failed while evaluating the input.
+    InferenceFailed,
+}
+
+impl ErrorCode {
+    const ALL: [ErrorCode; 6] = [
+        ErrorCode::InvalidParam,
+        ErrorCode::ModelLoadFailed,
+        ErrorCode::ContextFull,
+        ErrorCode::Aborted,
+        ErrorCode::Disposed,
+        ErrorCode::InferenceFailed,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidParam => "InvalidParam",
+            ErrorCode::ModelLoadFailed => "ModelLoadFailed",
+            ErrorCode::ContextFull => "ContextFull",
+            ErrorCode::Aborted => "Aborted",
+            ErrorCode::Disposed => "Disposed",
+            ErrorCode::InferenceFailed => "InferenceFailed",
+        }
+    }
+
+    fn status(&self) -> Status {
+        match self {
+            ErrorCode::InvalidParam => Status::InvalidArg,
+            ErrorCode::Aborted => Status::Cancelled,
+            _ => Status::GenericFailure,
+        }
+    }
+
+    /// Prefixes the message with the code, for errors sent as inference events.
+    pub fn message(&self, message: impl Display) -> String {
+        format!("[{}] {}", self.as_str(), message)
+    }
+
+    /// Builds the error for this code, its JS `code` property keeps the napi status.
+    pub fn error(&self, message: impl Display) -> napi::Error {
+        napi::Error::new(self.status(), self.message(message))
+    }
+
+    /// Splits a message built by `message` back into its code and text.
+    pub fn split_message(message: &str) -> Option<(ErrorCode, &str)> {
+        let (code, text) = message.strip_prefix('[')?.split_once("] ")?;
+        Self::ALL
+            .into_iter()
+            .find(|c| c.as_str() == code)
+            .map(|c| (c, text))
+    }
+}
+
+/// Deserializes the params given as a plain JS object, a mismatch is an `InvalidParam` error.
+pub fn parse_params<T: DeserializeOwned>(params: serde_json::Value) -> napi::Result<T> {
+    serde_json::from_value(params)
+        .map_err(|e| ErrorCode::InvalidParam.error(format!("Invalid params: {}", e)))
+}
@@ -0,0 +1,56 @@
+use std::sync::Arc;
+
+use tokio::sync::{
+    OwnedRwLockMappedWriteGuard, OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock,
+};
+
+use crate::error::ErrorCode;
+
+/// A loaded model shared between its JS wrapper and the tasks the wrapper spawned.
+/// `dispose` waits for the running tasks to release the model and frees it right away,
+/// every later access fails with the disposed error.
+pub struct ModelHandle<T>(Arc<RwLock<Option<T>>>);
+
+impl<T> Clone for ModelHandle<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> ModelHandle<T> {
+    pub fn new(model: T) -> Self {
+        Self(Arc::new(RwLock::new(Some(model))))
+    }
+
+    /// Shared access, for models that can serve concurrent calls.
+    pub async fn read(&self) -> napi::Result<OwnedRwLockReadGuard<Option<T>, T>> {
+        OwnedRwLockReadGuard::try_map(self.0.clone().read_owned().await, Option::as_ref)
+            .map_err(|_| disposed_error())
+    }
+
+    /// Exclusive access, for models that run one call at a time.
+    pub async fn write(&self) -> napi::Result<OwnedRwLockMappedWriteGuard<Option<T>, T>> {
+        OwnedRwLockWriteGuard::try_map(self.0.clone().write_owned().await, Option::as_mut)
+            .map_err(|_| disposed_error())
+    }
+
+    /// Same as `read`, for blocking threads.
+    pub fn blocking_read(&self) -> napi::Result<OwnedRwLockReadGuard<Option<T>, T>> {
+        futures::executor::block_on(self.read())
+    }
+
+    /// Same as `write`, for blocking threads.
+    pub fn blocking_write(&self) -> napi::Result<OwnedRwLockMappedWriteGuard<Option<T>, T>> {
+        futures::executor::block_on(self.write())
+    }
+
+    /// Waits for the calls holding the model and drops it, calling it again is a no-op.
+    pub async fn dispose(&self) {
+        self.0.write().await.take();
+    }
+}
+
+/// The error calls on a disposed model reject with.
+pub fn disposed_error() -> napi::Error {
+    ErrorCode::Disposed.error("The model has been disposed")
+}
@@ -1 +1,7 @@
+pub mod abort;
+pub mod callback;
+pub mod error;
+pub mod handle;
 pub mod logger;
+pub mod output;
+pub mod stream;
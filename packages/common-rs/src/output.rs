@@ -0,0 +1,213 @@
+/// Result of pushing generated text into a StopSequenceMatcher.
+pub enum StopMatch {
+    /// No stop sequence matched, the text can be sent to the caller.
+    Continue(String),
+    /// A stop sequence matched, the text before it can be sent and generation should stop.
+    Stop(String),
+}
+
+/// Matches stop sequences on the decoded text, so they are found no matter how they were tokenized.
+/// Text that could be the start of a stop sequence is held back until it either completes the
+/// stop sequence or stops matching, in which case it is released.
+pub struct StopSequenceMatcher {
+    stop_sequences: Vec<String>,
+    held: String,
+}
+
+impl StopSequenceMatcher {
+    pub fn new(stop_sequences: Vec<String>) -> Self {
+        Self {
+            stop_sequences: stop_sequences
+                .into_iter()
+                .filter(|stop_sequence| !stop_sequence.is_empty())
+                .collect(),
+            held: String::new(),
+        }
+    }
+
+    pub fn push(&mut self, text: &str) -> StopMatch {
+        self.held.push_str(text);
+
+        let matched = self
+            .stop_sequences
+            .iter()
+            .filter_map(|stop_sequence| self.held.find(stop_sequence.as_str()))
+            .min();
+
+        if let Some(index) = matched {
+            let output = self.held[..index].to_string();
+            self.held.clear();
+            return StopMatch::Stop(output);
+        }
+
+        let n_held = self.partial_match_len();
+        let output = self.held.drain(..self.held.len() - n_held).collect();
+        StopMatch::Continue(output)
+    }
+
+    /// Releases the text held back for a partial match.
+    pub fn flush(&mut self) -> String {
+        std::mem::take(&mut self.held)
+    }
+
+    // Length of the longest suffix of the held text that is a proper prefix of a stop sequence.
+    fn partial_match_len(&self) -> usize {
+        let held = self.held.as_str();
+
+        self.stop_sequences
+            .iter()
+            .filter_map(|stop_sequence| {
+                (1..stop_sequence.len())
+                    .rev()
+                    .filter(|n| *n <= held.len() && stop_sequence.is_char_boundary(*n))
+                    .find(|n| held.ends_with(&stop_sequence[..*n]))
+            })
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Buffers the bytes of generated tokens until they form valid UTF-8, so characters split across
+/// several tokens (CJK, emoji) are not sent as replacement characters.
+#[derive(Default)]
+pub struct Utf8Decoder {
+    buffer: Vec<u8>,
+}
+
+impl Utf8Decoder {
+    /// Returns the text that is complete so far, an incomplete trailing sequence is kept. Invalid
+    /// bytes become replacement characters.
+    pub fn push(&mut self, bytes: &[u8]) -> String {
+        self.buffer.extend_from_slice(bytes);
+        let mut text = String::new();
+
+        loop {
+            match std::str::from_utf8(&self.buffer) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    self.buffer.clear();
+                    return text;
+                }
+                Err(e) => {
+                    let valid_up_to = e.valid_up_to();
+                    text.push_str(&String::from_utf8_lossy(&self.buffer[..valid_up_to]));
+                    match e.error_len() {
+                        // The trailing sequence may still be completed by the next token.
+                        None => {
+                            self.buffer.drain(..valid_up_to);
+                            return text;
+                        }
+                        Some(n_invalid) => {
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            self.buffer.drain(..valid_up_to + n_invalid);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Releases whatever is buffered, invalid bytes become replacement characters.
+    pub fn flush(&mut self) -> String {
+        let text = String::from_utf8_lossy(&self.buffer).into_owned();
+        self.buffer.clear();
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pushes every piece and returns the text sent to the caller, and whether a stop matched.
+    fn run_matcher(stop_sequences: &[&str], pieces: &[&str]) -> (String, bool) {
+        let mut matcher =
+            StopSequenceMatcher::new(stop_sequences.iter().map(|s| s.to_string()).collect());
+        let mut output = String::new();
+
+        for piece in pieces {
+            match matcher.push(piece) {
+                StopMatch::Continue(text) => output.push_str(&text),
+                StopMatch::Stop(text) => {
+                    output.push_str(&text);
+                    return (output, true);
+                }
+            }
+        }
+        output.push_str(&matcher.flush());
+        (output, false)
+    }
+
+    #[test]
+    fn holds_back_partial_match() {
+        let mut matcher = StopSequenceMatcher::new(vec!["\nUSER:".to_string()]);
+
+        match matcher.push("Hello\nUS") {
+            StopMatch::Continue(text) => assert_eq!(text, "Hello"),
+            StopMatch::Stop(_) => panic!("stopped on a partial match"),
+        }
+        match matcher.push("A") {
+            StopMatch::Continue(text) => assert_eq!(text, "\nUSA"),
+            StopMatch::Stop(_) => panic!("stopped on a released match"),
+        }
+    }
+
+    #[test]
+    fn releases_partial_match_on_flush() {
+        assert_eq!(
+            run_matcher(&["\nUSER:"], &["Hello", "\nUS"]),
+            ("Hello\nUS".to_string(), false)
+        );
+    }
+
+    #[test]
+    fn matches_stop_sequence_split_across_tokens() {
+        assert_eq!(
+            run_matcher(&["\nUSER:"], &["Hi", "\n", "US", "ER", ":", " more"]),
+            ("Hi".to_string(), true)
+        );
+    }
+
+    #[test]
+    fn stops_at_earliest_stop_sequence() {
+        assert_eq!(
+            run_matcher(&["world", "lo"], &["Hello world"]),
+            ("Hel".to_string(), true)
+        );
+    }
+
+    #[test]
+    fn ignores_empty_stop_sequence() {
+        assert_eq!(run_matcher(&[""], &["Hello"]), ("Hello".to_string(), false));
+    }
+
+    #[test]
+    fn decodes_character_split_across_tokens() {
+        let mut decoder = Utf8Decoder::default();
+        let bytes = "你好".as_bytes();
+
+        assert_eq!(decoder.push(&bytes[..1]), "");
+        assert_eq!(decoder.push(&bytes[1..4]), "你");
+        assert_eq!(decoder.push(&bytes[4..]), "好");
+        assert_eq!(decoder.flush(), "");
+    }
+
+    #[test]
+    fn keeps_incomplete_sequence_after_invalid_byte() {
+        let mut decoder = Utf8Decoder::default();
+        let emoji = "😀".as_bytes();
+
+        let mut bytes = vec![b'a', 0xff];
+        bytes.extend_from_slice(&emoji[..2]);
+        assert_eq!(decoder.push(&bytes), "a\u{FFFD}");
+        assert_eq!(decoder.push(&emoji[2..]), "😀");
+    }
+
+    #[test]
+    fn flushes_incomplete_sequence_lossily() {
+        let mut decoder = Utf8Decoder::default();
+
+        assert_eq!(decoder.push(&"é".as_bytes()[..1]), "");
+        assert_eq!(decoder.flush(), "\u{FFFD}");
+    }
+}
@@ -0,0 +1,78 @@
+use futures::future::{self, Either};
+use tokio::sync::{mpsc, Mutex};
+
+use crate::abort::AbortFlag;
+
+/// The events every backend reports a failed inference with.
+pub trait InferenceEvent {
+    /// The Error event carrying `message`.
+    fn error(message: String) -> Self;
+    /// The End event of a failed inference.
+    fn failed() -> Self;
+}
+
+/// Runs `inference` on the calling thread, a failure is reported as an Error event followed by End.
+pub fn run_inference<R: InferenceEvent>(
+    send: impl Fn(R),
+    inference: impl FnOnce(&dyn Fn(R)) -> napi::Result<()>,
+) {
+    if let Err(e) = inference(&send) {
+        log::error!("Error in inference: {}", e.reason);
+        send(R::error(e.reason));
+        send(R::failed());
+    }
+}
+
+/// Creates a stream of inference results that buffers at most `buffer_size` of them, the
+/// generation waits for room until `abort` is raised.
+pub fn result_stream<T>(
+    buffer_size: Option<u32>,
+    abort: AbortFlag,
+) -> (ResultSender<T>, ResultStream<T>) {
+    let (sender, receiver) = mpsc::channel(buffer_size.unwrap_or(16).max(1) as usize);
+    let stream = ResultStream {
+        receiver: Mutex::new(receiver),
+        // The stream may outlive the generation, only the sender keeps the signal listener.
+        abort: abort.detached(),
+    };
+    (ResultSender { sender, abort }, stream)
+}
+
+/// The half of a result stream used by the thread running the inference.
+pub struct ResultSender<T> {
+    sender: mpsc::Sender<T>,
+    abort: AbortFlag,
+}
+
+impl<T> ResultSender<T> {
+    /// Waits for room in the buffer. Once aborted the stream may not be read anymore, so the
+    /// result is dropped instead of blocking the model. A dropped stream aborts the generation.
+    pub fn blocking_send(&self, result: T) {
+        let send = self.sender.send(result);
+        let aborted = self.abort.aborted();
+        futures::pin_mut!(send, aborted);
+
+        let sent = futures::executor::block_on(future::select(send, aborted));
+        if let Either::Left((Err(_), _)) = sent {
+            // The stream was dropped, stop generating.
+            self.abort.abort();
+        }
+    }
+}
+
+/// The half of a result stream read from JS, until `next` returns `None`.
+pub struct ResultStream<T> {
+    receiver: Mutex<mpsc::Receiver<T>>,
+    abort: AbortFlag,
+}
+
+impl<T> ResultStream<T> {
+    pub async fn next(&self) -> Option<T> {
+        self.receiver.lock().await.recv().await
+    }
+
+    /// Stops the generation, the results buffered so far can still be read.
+    pub fn abort(&self) {
+        self.abort.abort();
+    }
+}
@@ -1,4 +1,4 @@
-import { LLM, Llm, ModelType } from "../index";
+import { EmbeddingPooling, LLM, Llm, ModelType } from "../index";
 import path from "path";
 import fs from "fs";
 
@@ -14,6 +14,8 @@ const getWordEmbeddings = async (llm: LLM, prompt: string, file: string) => {
         repeatPenalty: 1,
         repeatLastN: 64,
         seed: 0,
+        embeddingPooling: EmbeddingPooling.Mean,
+        normalizeEmbedding: true,
     });
 
     fs.writeFileSync(
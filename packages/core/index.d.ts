@@ -13,17 +13,54 @@ export const enum ModelType {
 }
 export interface InferenceToken {
   token: string
-  completed: boolean
 }
 export const enum InferenceResultType {
   Data = 'Data',
   End = 'End',
   Error = 'Error'
 }
+export const enum FinishReason {
+  /** The model generated the end of text token */
+  Eos = 'Eos',
+  /** A stop sequence was generated, not reported by llm yet */
+  StopSequence = 'StopSequence',
+  /** num_predict tokens were generated */
+  Length = 'Length',
+  /** The context window is full */
+  ContextFull = 'ContextFull',
+  /** The inference was aborted by the caller */
+  Aborted = 'Aborted',
+  /** The inference failed, see the Error event */
+  Error = 'Error'
+}
+export interface InferenceUsage {
+  /** Number of tokens in the prompt */
+  nPromptTokens: number
+  /** Number of tokens generated */
+  nGeneratedTokens: number
+}
 export interface InferenceResult {
   type: InferenceResultType
   message?: string
   data?: InferenceToken
+  /** Why the generation ended, set on the End event */
+  finishReason?: FinishReason
+  /** Token counts of the request, set on the End event */
+  usage?: InferenceUsage
+  /** The full generated text, set on the End event */
+  text?: string
+}
+export const enum EmbeddingPooling {
+  /** Embedding of the last prompt token */
+  Last = 'Last',
+  /** Mean of the embeddings of every prompt token */
+  Mean = 'Mean'
+}
+export interface EmbeddingProgress {
+  /** number of texts embedded so far */
+  nProcessed: number
+  /** number of texts in the batch */
+  nTotal: number
 }
 export interface TokenBias {
   tokenId: TokenId
@@ -160,6 +197,16 @@ export interface Generate {
    * Default is None
    */
   saveSession?: string
+  /**
+   * How the token embeddings are pooled into one embedding
+   * Default is Last
+   */
+  embeddingPooling: EmbeddingPooling
+  /**
+   * L2 normalize the embedding
+   * Default is false
+   */
+  normalizeEmbedding: boolean
 }
 export const enum ElementType {
   /** All tensors are stored as f32. */
@@ -188,12 +235,38 @@ export const enum ElementType {
 export function convert(path: string, elementType: ElementType): Promise<void>
 export type LLM = Llm
 export class Llm {
-  /** Create a new LLM instance. */
-  static load(config: ModelLoad, enableLogger: boolean): Promise<Llm>
+  /** Create a new LLM instance, aborting `signal` rejects with an error whose code is `Cancelled`. */
+  static load(config: ModelLoad, enableLogger: boolean, signal?: AbortSignal): Promise<Llm>
   /** Get the tokenized result as number array, the result will be returned as Promise of number array. */
-  tokenize(params: string): Promise<Array<number>>
+  tokenize(params: string, signal?: AbortSignal): Promise<Array<number>>
   /** Get the embedding result as number array, the result will be returned as Promise of number array. */
-  getWordEmbeddings(params: Partial<Generate>): Promise<Array<number>>
-  /** Streaming the inference result as string, the result will be passed to the callback function. Will return a function to abort the inference. */
-  inference(params: Partial<Generate>, callback: (result: InferenceResult) => void): () => void
+  getWordEmbeddings(params: Partial<Generate>, signal?: AbortSignal): Promise<Array<number>>
+  /**
+   * Get the embeddings of a list of texts in one call, `params.prompt` is ignored.
+   * `progress` is called after each text is embedded.
+   */
+  getEmbeddings(texts: Array<string>, params: Partial<Generate>, progress?: (progress: EmbeddingProgress) => void, signal?: AbortSignal): Promise<number[][]>
+  /**
+   * Same as `inference`, but the results are pulled from the returned stream. At most
+   * `buffer_size` results are buffered, the generation waits until they are read. Aborting
+   * `signal`, or calling `abort` on the stream, stops the inference.
+   */
+  inferenceStream(params: Partial<Generate>, bufferSize?: number | undefined | null, signal?: AbortSignal): InferenceStream
+  /**
+   * Streaming the inference result as string, the result will be passed to the callback function. Will return a function to abort the inference.
+   * Aborting `signal` stops the inference as well.
+   */
+  inference(params: Partial<Generate>, callback: (result: InferenceResult) => void, signal?: AbortSignal): () => void
+  /**
+   * Waits for the running calls to finish and frees the model right away, instead of when
+   * the object is garbage collected. Later calls reject, disposing twice is a no-op.
+   */
+  dispose(): Promise<void>
+}
+/** Inference results of `LLM.inferenceStream`, read with `next` until it resolves null. */
+export class InferenceStream {
+  /** Resolves the next result, or null once the End event has been read. */
+  next(): Promise<InferenceResult | null>
+  /** Stops the generation, the results produced so far can still be read. */
+  abort(): void
 }
@@ -252,10 +252,13 @@ if (!nativeBinding) {
   throw new Error(`Failed to load native binding`)
 }
 
-const { ModelType, InferenceResultType, ElementType, convert, Llm } = nativeBinding
+const { ModelType, InferenceResultType, FinishReason, EmbeddingPooling, ElementType, convert, Llm, InferenceStream } = nativeBinding
 
 module.exports.ModelType = ModelType
 module.exports.InferenceResultType = InferenceResultType
+module.exports.FinishReason = FinishReason
+module.exports.EmbeddingPooling = EmbeddingPooling
 module.exports.ElementType = ElementType
 module.exports.convert = convert
 module.exports.Llm = Llm
+module.exports.InferenceStream = InferenceStream
@@ -6,6 +6,10 @@ use std::{
 };
 
 use anyhow::{Error, Result};
+use common_rs::{
+    abort::{aborted_error, AbortFlag},
+    error::ErrorCode,
+};
 use llm::{
     InferenceError, InferenceFeedback, InferenceParameters, InferenceSession,
     InferenceSessionConfig, Model, ModelKVMemoryType, OutputRequest, TokenBias,
@@ -14,7 +18,8 @@ use rand::SeedableRng;
 use zstd::{zstd_safe::CompressionLevel, Decoder, Encoder};
 
 use crate::types::{
-    Generate, InferenceResult, InferenceResultType, InferenceToken, ModelLoad, ModelType,
+    EmbeddingPooling, FinishReason, Generate, InferenceResult, InferenceResultType, InferenceToken,
+    InferenceUsage, ModelLoad, ModelType,
 };
 
 const CACHE_COMPRESSION_LEVEL: CompressionLevel = 1;
@@ -28,7 +33,13 @@ pub struct LLMContext {
 // }
 
 impl LLMContext {
-    pub async fn load_model(params: &ModelLoad) -> Result<LLMContext, napi::Error> {
+    /// The load itself cannot be interrupted, `abort` is checked before and after it.
+    pub async fn load_model(
+        params: &ModelLoad,
+        abort: &AbortFlag,
+    ) -> Result<LLMContext, napi::Error> {
+        abort.check()?;
+
         let model = match params.model_type {
             ModelType::Llama => params.load::<llm::models::Llama>(),
             ModelType::Bloom => params.load::<llm::models::Bloom>(),
@@ -38,6 +49,8 @@ impl LLMContext {
             ModelType::Mpt => params.load::<llm::models::Mpt>(),
         }?;
 
+        abort.check()?;
+
         Ok(LLMContext { model })
     }
 
@@ -45,7 +58,7 @@ impl LLMContext {
         let vocab = self.model.vocabulary();
         let tokens = vocab
             .tokenize(text, false)
-            .map_err(|e| napi::Error::from_reason(format!("Failed to tokenize: {}", e)))?;
+            .map_err(|e| ErrorCode::InvalidParam.error(format!("Failed to tokenize: {}", e)))?;
         let tokens = tokens.iter().map(|(_, tid)| *tid).collect::<Vec<_>>();
 
         Ok(tokens)
@@ -150,51 +163,117 @@ impl LLMContext {
         self.read_or_create_session(load_session, inference_session_params)
     }
 
-    pub async fn get_word_embedding(&self, params: &Generate) -> Result<Vec<f64>, napi::Error> {
+    pub async fn get_word_embedding(
+        &self,
+        params: &Generate,
+        abort: &AbortFlag,
+    ) -> Result<Vec<f64>, napi::Error> {
+        let inference_params = self.get_inference_params(params);
+
+        self.embed(&params.prompt, params, &inference_params, abort)
+    }
+
+    /// Embeds every text with the same inference parameters, `params.prompt` is ignored.
+    /// llm cannot rewind a session, so each text still gets a fresh one.
+    pub fn get_embeddings(
+        &self,
+        texts: &[String],
+        params: &Generate,
+        abort: &AbortFlag,
+        mut on_progress: impl FnMut(usize),
+    ) -> Result<Vec<Vec<f64>>, napi::Error> {
+        let inference_params = self.get_inference_params(params);
+        let mut embeddings = Vec::with_capacity(texts.len());
+
+        for text in texts.iter() {
+            embeddings.push(self.embed(text, params, &inference_params, abort)?);
+            on_progress(embeddings.len());
+        }
+
+        Ok(embeddings)
+    }
+
+    fn embed(
+        &self,
+        prompt: &str,
+        params: &Generate,
+        inference_params: &InferenceParameters,
+        abort: &AbortFlag,
+    ) -> Result<Vec<f64>, napi::Error> {
         let mut session = self.start_session(params).map_err(|e| {
-            napi::Error::from_reason(format!("Failed to start inference session: {}", e))
+            ErrorCode::InferenceFailed.error(format!("Failed to start inference session: {}", e))
         })?;
-        let inference_params = self.get_inference_params(params);
         let model = self.model.as_ref();
-        let prompt_for_feed = format!(" {}", params.prompt);
 
-        if let Err(InferenceError::ContextFull) = session.feed_prompt::<Infallible, &str>(
-            model,
-            &inference_params,
-            prompt_for_feed.as_str(),
-            &mut Default::default(),
-            |_| Ok(InferenceFeedback::Continue),
-        ) {
-            return Err(napi::Error::from_reason("Context window full."));
+        let tokens = model
+            .vocabulary()
+            .tokenize(prompt, true)
+            .map_err(|e| ErrorCode::InvalidParam.error(format!("Failed to tokenize: {}", e)))?
+            .iter()
+            .map(|(_, tid)| *tid)
+            .collect::<Vec<_>>();
+
+        if tokens.is_empty() {
+            return Err(ErrorCode::InvalidParam.error("Cannot embed an empty input"));
         }
 
-        let end_token = self.tokenize("\n").await.map_err(|e| {
-            napi::Error::from_reason(format!("Failed to tokenize end token: {}", e))
-        })?;
+        if tokens.len() > model.n_context_tokens() {
+            return Err(ErrorCode::ContextFull.error("Context window full."));
+        }
+
+        // llm only returns the embedding of the last token of every evaluation, so mean pooling
+        // has to feed the prompt one token at a time.
+        let chunk_size = match params.embedding_pooling {
+            EmbeddingPooling::Last => inference_params.n_batch.max(1),
+            EmbeddingPooling::Mean => 1,
+        };
 
         let mut output_request = OutputRequest {
             all_logits: None,
             embeddings: Some(Vec::new()),
         };
+        let mut embedding: Vec<f64> = Vec::new();
 
-        model.evaluate(
-            &mut session,
-            &inference_params,
-            &end_token,
-            &mut output_request,
-        );
+        for chunk in tokens.chunks(chunk_size) {
+            if abort.is_aborted() {
+                return Err(aborted_error());
+            }
+
+            model.evaluate(&mut session, inference_params, chunk, &mut output_request);
+
+            let token_embedding = output_request.embeddings.as_deref().unwrap_or_default();
 
-        let output: Option<Vec<f64>> = output_request
-            .embeddings
-            .map(|embd| embd.into_iter().map(|data| data.into()).collect());
+            match params.embedding_pooling {
+                EmbeddingPooling::Last => {
+                    embedding = token_embedding.iter().map(|x| *x as f64).collect();
+                }
+                EmbeddingPooling::Mean => {
+                    if embedding.is_empty() {
+                        embedding = vec![0.0; token_embedding.len()];
+                    }
+                    for (sum, x) in embedding.iter_mut().zip(token_embedding.iter()) {
+                        *sum += *x as f64 / tokens.len() as f64;
+                    }
+                }
+            }
+        }
+
+        if params.normalize_embedding {
+            let norm = embedding.iter().map(|x| x * x).sum::<f64>().sqrt();
+            if norm > 0.0 {
+                embedding.iter_mut().for_each(|x| *x /= norm);
+            }
+        }
 
-        Ok(output.unwrap_or(Vec::new()))
+        Ok(embedding)
     }
 
+    /// Runs the inference, halting at the next token once `abort` is set.
     pub fn inference(
         &self,
         params: &Generate,
-        callback: impl Fn(InferenceResult) -> InferenceFeedback,
+        abort: &AbortFlag,
+        callback: impl Fn(InferenceResult),
     ) -> Result<(), napi::Error> {
         let prompt = &params.prompt;
         let inference_params = self.get_inference_params(params);
@@ -211,7 +290,7 @@ impl LLMContext {
         let seed = params.seed.map(|seed| seed as u64);
 
         let mut session = self.start_session(params).map_err(|e| {
-            napi::Error::from_reason(format!("Failed to start inference session: {}", e))
+            ErrorCode::InferenceFailed.error(format!("Failed to start inference session: {}", e))
         })?;
 
         let maximum_token_count = if feed_prompt_only {
@@ -226,6 +305,31 @@ impl LLMContext {
             rand::rngs::StdRng::from_entropy()
         };
 
+        let n_prompt_tokens = model
+            .vocabulary()
+            .tokenize(prompt, true)
+            .map(|tokens| tokens.len() as u32)
+            .unwrap_or_default();
+        // Everything sent to the caller, reported with the End event.
+        let mut text = String::new();
+        let mut n_generated_tokens: u32 = 0;
+        let mut is_eot = false;
+        let mut is_halted = false;
+
+        let mut send_token = |token: &str| {
+            text.push_str(token);
+            callback(InferenceResult {
+                r#type: InferenceResultType::Data,
+                message: None,
+                data: Some(InferenceToken {
+                    token: token.to_string(),
+                }),
+                finish_reason: None,
+                usage: None,
+                text: None,
+            });
+        };
+
         let res = session.infer::<Infallible>(
             model,
             &mut rng,
@@ -236,77 +340,63 @@ impl LLMContext {
                 maximum_token_count,
             },
             &mut Default::default(),
-            |r| match &r {
-                llm::InferenceResponse::PromptToken(t) => {
-                    let to_send = InferenceResult {
-                        r#type: InferenceResultType::Data,
-                        message: None,
-                        data: Some(InferenceToken {
-                            token: t.to_string(),
-                            completed: false,
-                        }),
-                    };
-
-                    Ok(if feed_prompt {
-                        InferenceFeedback::Continue
-                    } else {
-                        callback(to_send)
-                    })
+            |r| {
+                if abort.is_aborted() {
+                    is_halted = true;
+                    return Ok(InferenceFeedback::Halt);
                 }
-                llm::InferenceResponse::InferredToken(t) => {
-                    let to_send = InferenceResult {
-                        r#type: InferenceResultType::Data,
-                        message: None,
-                        data: Some(InferenceToken {
-                            token: t.to_string(),
-                            completed: false,
-                        }),
-                    };
-
-                    Ok(if feed_prompt_only {
-                        InferenceFeedback::Continue
-                    } else {
-                        callback(to_send)
-                    })
+
+                match &r {
+                    llm::InferenceResponse::PromptToken(t) => {
+                        if !feed_prompt {
+                            send_token(t);
+                        }
+                    }
+                    llm::InferenceResponse::InferredToken(t) => {
+                        n_generated_tokens += 1;
+                        if !feed_prompt_only {
+                            send_token(t);
+                        }
+                    }
+                    llm::InferenceResponse::SnapshotToken(_) => {}
+                    llm::InferenceResponse::EotToken => {
+                        is_eot = true;
+                    }
                 }
-                llm::InferenceResponse::SnapshotToken(_) => Ok(InferenceFeedback::Continue),
-                llm::InferenceResponse::EotToken => Ok(InferenceFeedback::Continue),
+
+                Ok(InferenceFeedback::Continue)
             },
         );
 
-        match res {
-            Ok(_state) => {
-                let to_send = InferenceResult {
-                    r#type: InferenceResultType::Data,
-                    message: None,
-                    data: Some(InferenceToken {
-                        token: "\n\n<end>\n".to_string(),
-                        completed: true,
-                    }),
-                };
-
-                callback(to_send);
-            }
+        let finish_reason = match res {
+            Ok(_) if is_halted => FinishReason::Aborted,
+            Ok(_) if is_eot => FinishReason::Eos,
+            Ok(_) => FinishReason::Length,
+            Err(InferenceError::EndOfText) => FinishReason::Eos,
+            Err(InferenceError::ContextFull) => FinishReason::ContextFull,
             Err(error) => {
                 let message = match error {
-                    InferenceError::EndOfText => "End of text.".to_string(),
-                    InferenceError::ContextFull => {
-                        "Context window full, stopping inference.".to_string()
+                    InferenceError::TokenizationFailed(_) => {
+                        ErrorCode::InvalidParam.message("Tokenization failed.")
                     }
-                    InferenceError::TokenizationFailed(_) => "Tokenization failed.".to_string(),
-                    InferenceError::UserCallback(_) => "Inference failed.".to_string(),
+                    _ => ErrorCode::InferenceFailed.message("Inference failed."),
                 };
                 callback(InferenceResult {
                     r#type: InferenceResultType::Error,
                     message: Some(message),
                     data: None,
+                    finish_reason: None,
+                    usage: None,
+                    text: None,
                 });
+                FinishReason::Error
             }
-        }
+        };
 
         if let Some(session_path) = params.save_session.as_ref() {
             self.write_session(session, session_path).map_err(|e| {
-                napi::Error::from_reason(format!("Failed to write inference session: {}", e))
+                ErrorCode::InferenceFailed
+                    .error(format!("Failed to write inference session: {}", e))
             })?;
         }
 
@@ -314,6 +404,12 @@ impl LLMContext {
             r#type: InferenceResultType::End,
             message: None,
             data: None,
+            finish_reason: Some(finish_reason),
+            usage: Some(InferenceUsage {
+                n_prompt_tokens,
+                n_generated_tokens,
+            }),
+            text: Some(text),
         });
 
         Ok(())
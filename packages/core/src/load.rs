@@ -2,6 +2,7 @@ use std::path::Path;
 
 use crate::types::ModelLoad;
 use anyhow::Result;
+use common_rs::error::ErrorCode;
 use llm::{load, LoadProgress, Model, ModelParameters};
 
 impl ModelLoad {
@@ -61,7 +62,7 @@ impl ModelLoad {
                 log::info!("Applied Lora: {}", name);
             }
         })
-        .map_err(|e| napi::Error::from_reason(format!("{}", e)))?;
+        .map_err(|e| ErrorCode::ModelLoadFailed.error(e))?;
 
         Ok(Box::new(model))
     }
@@ -0,0 +1,301 @@
+use std::path::Path;
+
+use common_rs::{
+    abort::AbortFlag,
+    callback::Callback,
+    error::{parse_params, ErrorCode},
+    handle::ModelHandle,
+    logger::LLamaLogger,
+    stream::{result_stream, run_inference, InferenceEvent, ResultStream},
+};
+use napi::{bindgen_prelude::*, JsFunction, JsObject};
+
+use crate::{
+    context::LLMContext,
+    types::{
+        EmbeddingProgress, FinishReason, Generate, InferenceResult, InferenceResultType, ModelLoad,
+    },
+};
+
+#[napi]
+pub enum ElementType {
+    /// All tensors are stored as f32.
+    F32,
+    /// All tensors are mostly stored as `f16`, except for the 1D tensors (32-bit).
+    MostlyF16,
+    /// All tensors are mostly stored as `Q4_0`, except for the 1D tensors (32-bit).
+    MostlyQ4_0,
+    /// All tensors are mostly stored as `Q4_1`, except for the 1D tensors (32-bit)
+    MostlyQ4_1,
+    /// All tensors are mostly stored as `Q4_1`, except for the 1D tensors (32-bit)
+    /// and the `tok_embeddings.weight` (f16) and `output.weight` tensors (f16).
+    MostlyQ4_1SomeF16,
+    /// All tensors are mostly stored as `Q4_2`, except for the 1D tensors (32-bit).
+    MostlyQ4_2,
+    /// All tensors are mostly stored as `Q8_0`, except for the 1D tensors (32-bit).
+    MostlyQ8_0,
+    /// All tensors are mostly stored as `Q5_0`, except for the 1D tensors (32-bit).
+    MostlyQ5_0,
+    /// All tensors are mostly stored as `Q5_1`, except for the 1D tensors (32-bit).
+    MostlyQ5_1,
+}
+
+impl From<ElementType> for llm::FileTypeFormat {
+    fn from(element_type: ElementType) -> Self {
+        match element_type {
+            ElementType::F32 => llm::FileTypeFormat::F32,
+            ElementType::MostlyF16 => llm::FileTypeFormat::MostlyF16,
+            ElementType::MostlyQ4_0 => llm::FileTypeFormat::MostlyQ4_0,
+            ElementType::MostlyQ4_1 => llm::FileTypeFormat::MostlyQ4_1,
+            ElementType::MostlyQ4_1SomeF16 => llm::FileTypeFormat::MostlyQ4_1SomeF16,
+            ElementType::MostlyQ4_2 => llm::FileTypeFormat::MostlyQ4_2,
+            ElementType::MostlyQ8_0 => llm::FileTypeFormat::MostlyQ8_0,
+            ElementType::MostlyQ5_0 => llm::FileTypeFormat::MostlyQ5_0,
+            ElementType::MostlyQ5_1 => llm::FileTypeFormat::MostlyQ5_1,
+        }
+    }
+}
+
+/// Not implemented yet.
+#[napi(js_name = "convert")]
+pub async fn convert(path: String, _element_type: ElementType) -> Result<()> {
+    let handle = tokio::task::spawn_blocking(move || {
+        let path = Path::new(path.as_str());
+        println!("path: {:?}", path);
+        // convert_pth_to_ggml is removed from llm
+        // convert_pth_to_ggml(path, element_type.into());
+    })
+    .await;
+    match handle {
+        Ok(_) => Ok(()),
+        Err(_) => Err(napi::Error::new(
+            napi::Status::GenericFailure,
+            "Failed to convert model".to_string(),
+        )),
+    }
+}
+
+#[napi]
+pub struct LLM {
+    llm: ModelHandle<LLMContext>,
+}
+
+/// LLM class is a Rust wrapper for llm-rs.
+#[napi]
+impl LLM {
+    /// Create a new LLM instance, aborting `signal` rejects with an error whose code is `Cancelled`.
+    #[napi(ts_return_type = "Promise<Llm>")]
+    pub fn load(
+        env: Env,
+        config: ModelLoad,
+        enable_logger: bool,
+        #[napi(ts_arg_type = "AbortSignal")] signal: Option<JsObject>,
+    ) -> Result<JsObject> {
+        let logger = LLamaLogger::get_singleton();
+        logger.set_enabled(enable_logger);
+
+        let abort = AbortFlag::from_signal(&env, signal)?;
+
+        env.execute_tokio_future(
+            async move { LLMContext::load_model(&config, &abort).await },
+            |_, llm| {
+                Ok(LLM {
+                    llm: ModelHandle::new(llm),
+                })
+            },
+        )
+    }
+
+    /// Get the tokenized result as number array, the result will be returned as Promise of number array.
+    #[napi(ts_return_type = "Promise<Array<number>>")]
+    pub fn tokenize(
+        &self,
+        env: Env,
+        params: String,
+        #[napi(ts_arg_type = "AbortSignal")] signal: Option<JsObject>,
+    ) -> Result<JsObject> {
+        let abort = AbortFlag::from_signal(&env, signal)?;
+        let llm = self.llm.clone();
+
+        env.execute_tokio_future(
+            async move {
+                let llm = llm.read().await?;
+                abort.check()?;
+                llm.tokenize(&params).await
+            },
+            |_, tokens| Ok(tokens),
+        )
+    }
+
+    /// Get the embedding result as number array, the result will be returned as Promise of number array.
+    #[napi(ts_return_type = "Promise<Array<number>>")]
+    pub fn get_word_embeddings(
+        &self,
+        env: Env,
+        #[napi(ts_arg_type = "Partial<Generate>")] params: serde_json::Value,
+        #[napi(ts_arg_type = "AbortSignal")] signal: Option<JsObject>,
+    ) -> Result<JsObject> {
+        let params = parse_params::<Generate>(params)?;
+        let abort = AbortFlag::from_signal(&env, signal)?;
+        let llm = self.llm.clone();
+
+        env.execute_tokio_future(
+            async move { llm.read().await?.get_word_embedding(&params, &abort).await },
+            |_, embedding| Ok(embedding),
+        )
+    }
+
+    /// Get the embeddings of a list of texts in one call, `params.prompt` is ignored.
+    /// `progress` is called after each text is embedded.
+    #[napi(ts_return_type = "Promise<number[][]>")]
+    pub fn get_embeddings(
+        &self,
+        env: Env,
+        texts: Vec<String>,
+        #[napi(ts_arg_type = "Partial<Generate>")] params: serde_json::Value,
+        #[napi(ts_arg_type = "(progress: EmbeddingProgress) => void")] progress: Option<JsFunction>,
+        #[napi(ts_arg_type = "AbortSignal")] signal: Option<JsObject>,
+    ) -> Result<JsObject> {
+        let params = parse_params::<Generate>(params)?;
+        let abort = AbortFlag::from_signal(&env, signal)?;
+        let progress = progress.as_ref().map(Callback::new).transpose()?;
+
+        let llm = self.llm.clone();
+        let n_total = texts.len() as u32;
+
+        env.execute_tokio_future(
+            async move {
+                let llm = llm.read().await?;
+
+                tokio::task::spawn_blocking(move || {
+                    llm.get_embeddings(&texts, &params, &abort, |n_processed| {
+                        if let Some(progress) = &progress {
+                            progress.call(EmbeddingProgress {
+                                n_processed: n_processed as u32,
+                                n_total,
+                            });
+                        }
+                    })
+                })
+                .await
+                .map_err(|e| {
+                    ErrorCode::InferenceFailed.error(format!("Failed to run embedding: {}", e))
+                })?
+            },
+            |_, embeddings| Ok(embeddings),
+        )
+    }
+
+    /// Same as `inference`, but the results are pulled from the returned stream. At most
+    /// `buffer_size` results are buffered, the generation waits until they are read. Aborting
+    /// `signal`, or calling `abort` on the stream, stops the inference.
+    #[napi]
+    pub fn inference_stream(
+        &self,
+        env: Env,
+        #[napi(ts_arg_type = "Partial<Generate>")] params: serde_json::Value,
+        buffer_size: Option<u32>,
+        #[napi(ts_arg_type = "AbortSignal")] signal: Option<JsObject>,
+    ) -> Result<InferenceStream> {
+        let params = parse_params::<Generate>(params)?;
+        let abort = AbortFlag::from_signal(&env, signal)?;
+        let (sender, stream) = result_stream(buffer_size, abort.clone());
+        let llm = self.llm.clone();
+
+        tokio::task::spawn_blocking(move || {
+            run_inference(
+                |result| sender.blocking_send(result),
+                |send| llm.blocking_read()?.inference(&params, &abort, send),
+            );
+        });
+
+        Ok(InferenceStream { stream })
+    }
+
+    /// Streaming the inference result as string, the result will be passed to the callback function. Will return a function to abort the inference.
+    /// Aborting `signal` stops the inference as well.
+    #[napi(ts_return_type = "() => void")]
+    pub fn inference(
+        &self,
+        env: Env,
+        #[napi(ts_arg_type = "Partial<Generate>")] params: serde_json::Value,
+        #[napi(ts_arg_type = "(result: InferenceResult) => void")] callback: JsFunction,
+        #[napi(ts_arg_type = "AbortSignal")] signal: Option<JsObject>,
+    ) -> Result<JsFunction> {
+        let params = parse_params::<Generate>(params)?;
+        let callback = Callback::new(&callback)?;
+
+        let llm = self.llm.clone();
+
+        let abort = AbortFlag::from_signal(&env, signal)?;
+        {
+            let abort = abort.clone();
+            tokio::task::spawn_blocking(move || {
+                run_inference(
+                    |result| callback.call(result),
+                    |send| llm.blocking_read()?.inference(&params, &abort, send),
+                );
+            });
+        }
+
+        // The returned function may outlive the task, it should not keep the listener on `signal`.
+        let abort = abort.detached();
+        env.create_function_from_closure("abort_inference", move |_| {
+            abort.abort();
+            Ok(())
+        })
+    }
+
+    /// Waits for the running calls to finish and frees the model right away, instead of when
+    /// the object is garbage collected. Later calls reject, disposing twice is a no-op.
+    #[napi]
+    pub async fn dispose(&self) -> Result<()> {
+        self.llm.dispose().await;
+        Ok(())
+    }
+}
+
+impl InferenceEvent for InferenceResult {
+    fn error(message: String) -> Self {
+        InferenceResult {
+            r#type: InferenceResultType::Error,
+            message: Some(message),
+            data: None,
+            finish_reason: None,
+            usage: None,
+            text: None,
+        }
+    }
+
+    fn failed() -> Self {
+        InferenceResult {
+            r#type: InferenceResultType::End,
+            message: None,
+            data: None,
+            finish_reason: Some(FinishReason::Error),
+            usage: None,
+            text: None,
+        }
+    }
+}
+
+/// Inference results of `LLM.inferenceStream`, read with `next` until it resolves null.
+#[napi]
+pub struct InferenceStream {
+    stream: ResultStream<InferenceResult>,
+}
+
+#[napi]
+impl InferenceStream {
+    /// Resolves the next result, or null once the End event has been read.
+    #[napi]
+    pub async fn next(&self) -> Result<Option<InferenceResult>> {
+        Ok(self.stream.next().await)
+    }
+
+    /// Stops the generation, the results produced so far can still be read.
+    #[napi]
+    pub fn abort(&self) {
+        self.stream.abort();
+    }
+}
@@ -3,6 +3,8 @@ use napi::bindgen_prelude::*;
 use serde::{Deserialize, Serialize};
 
 #[napi(string_enum)]
+// napi derives Copy and Clone for enums, but not with the `noop` feature.
+#[cfg_attr(feature = "noop", derive(Clone, Copy))]
 #[derive(Debug)]
 pub enum ModelType {
     Llama,
@@ -17,10 +19,10 @@ pub enum ModelType {
 #[derive(Clone, Debug)]
 pub struct InferenceToken {
     pub token: String,
-    pub completed: bool,
 }
 
 #[napi(string_enum)]
+#[cfg_attr(feature = "noop", derive(Clone, Copy))]
 #[derive(Debug)]
 pub enum InferenceResultType {
     Data,
@@ -28,12 +30,63 @@ pub enum InferenceResultType {
     Error,
 }
 
+#[napi(string_enum)]
+#[cfg_attr(feature = "noop", derive(Clone, Copy))]
+#[derive(Debug)]
+pub enum FinishReason {
+    /// The model generated the end of text token
+    Eos,
+    /// A stop sequence was generated, not reported by llm yet
+    StopSequence,
+    /// num_predict tokens were generated
+    Length,
+    /// The context window is full
+    ContextFull,
+    /// The inference was aborted by the caller
+    Aborted,
+    /// The inference failed, see the Error event
+    Error,
+}
+
+#[napi(object)]
+#[derive(Clone, Debug)]
+pub struct InferenceUsage {
+    /// Number of tokens in the prompt
+    pub n_prompt_tokens: u32,
+    /// Number of tokens generated
+    pub n_generated_tokens: u32,
+}
+
 #[napi(object)]
 #[derive(Clone, Debug)]
 pub struct InferenceResult {
     pub r#type: InferenceResultType,
     pub message: Option<String>,
     pub data: Option<InferenceToken>,
+    /// Why the generation ended, set on the End event
+    pub finish_reason: Option<FinishReason>,
+    /// Token counts of the request, set on the End event
+    pub usage: Option<InferenceUsage>,
+    /// The full generated text, set on the End event
+    pub text: Option<String>,
+}
+
+#[napi(string_enum)]
+#[cfg_attr(feature = "noop", derive(Clone, Copy))]
+#[derive(Debug, Serialize, Deserialize)]
+pub enum EmbeddingPooling {
+    /// Embedding of the last prompt token
+    Last,
+    /// Mean of the embeddings of every prompt token
+    Mean,
+}
+
+#[napi(object)]
+pub struct EmbeddingProgress {
+    /// number of texts embedded so far
+    pub n_processed: u32,
+    /// number of texts in the batch
+    pub n_total: u32,
 }
 
 #[napi(object)]
@@ -162,6 +215,14 @@ pub struct Generate {
     /// Persist session path
     /// Default is None
     pub save_session: Option<String>,
+
+    /// How the token embeddings are pooled into one embedding
+    /// Default is Last
+    pub embedding_pooling: EmbeddingPooling,
+
+    /// L2 normalize the embedding
+    /// Default is false
+    pub normalize_embedding: bool,
 }
 
 impl Default for Generate {
@@ -184,6 +245,8 @@ impl Default for Generate {
             feed_prompt_only: false,
             load_session: None,
             save_session: None,
+            embedding_pooling: EmbeddingPooling::Last,
+            normalize_embedding: false,
         }
     }
 }
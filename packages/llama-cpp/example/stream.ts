@@ -0,0 +1,40 @@
+import { LLama, Generate, InferenceResultType } from "../index";
+import path from "path";
+
+const run = async () => {
+    const llama = await LLama.load(
+        {
+            modelPath: path.resolve(process.cwd(), "../../ggml-vic7b-q5_1.bin"),
+        },
+        true
+    );
+
+    const template = `Who is the president of the United States?`;
+
+    const prompt = `A chat between a user and an assistant.
+USER: ${template}
+ASSISTANT:`;
+
+    const params: Generate = {
+        nThreads: 4,
+        nTokPredict: 2048,
+        topK: 40,
+        topP: 0.1,
+        temp: 0.2,
+        repeatPenalty: 1,
+        prompt,
+    };
+
+    // At most 8 results are buffered, generation waits while stdout is slow.
+    const stream = llama.inferenceStream(params, 8);
+
+    for (let result = await stream.next(); result; result = await stream.next()) {
+        if (result.type === InferenceResultType.Data) {
+            process.stdout.write(result.data?.token ?? "");
+        } else if (result.type === InferenceResultType.End) {
+            console.log(`\n\nfinished: ${result.finishReason}`);
+        }
+    }
+};
+
+run();
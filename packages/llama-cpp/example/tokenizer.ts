@@ -0,0 +1,19 @@
+import { LLamaTokenizer } from "../index";
+import path from "path";
+
+const run = async () => {
+    const tokenizer = await LLamaTokenizer.load(
+        path.resolve(process.cwd(), "../../ggml-vic7b-q5_1.bin"),
+        false
+    );
+
+    const template = `Who is the president of the United States?`;
+
+    const tokens = tokenizer.tokenize(template, true);
+
+    console.log(tokens);
+    console.log(tokenizer.detokenize(tokens));
+    console.log(tokenizer.countTokens(template));
+};
+
+run();
@@ -5,25 +5,104 @@
 
 export interface InferenceToken {
   token: string
-  completed: boolean
+}
+export interface InferenceProgress {
+  /** number of prompt tokens evaluated so far */
+  nEvaluated: number
+  /** number of prompt tokens to evaluate */
+  nTotal: number
 }
 export const enum InferenceResultType {
   Error = 'Error',
   Data = 'Data',
+  Progress = 'Progress',
   End = 'End'
 }
+export const enum FinishReason {
+  /** the model generated the end of sequence token */
+  Eos = 'Eos',
+  /** the stop sequence was generated */
+  StopSequence = 'StopSequence',
+  /** n_tok_predict tokens were generated */
+  Length = 'Length',
+  /** the prompt does not fit in the context */
+  ContextFull = 'ContextFull',
+  /** the inference was aborted by the caller */
+  Aborted = 'Aborted',
+  /** the inference failed, see the Error event */
+  Error = 'Error'
+}
+export interface InferenceUsage {
+  /** number of tokens in the prompt */
+  nPromptTokens: number
+  /** number of tokens generated */
+  nGeneratedTokens: number
+}
+export interface InferenceTimings {
+  /**
+   * time the model took to load, in milliseconds. It was loaded once before the first request,
+   * so this is the same on every request and not included in `total_ms`
+   */
+  loadMs: number
+  /** time spent evaluating the prompt, including re-evaluation after context swaps */
+  promptEvalMs: number
+  /** number of prompt tokens evaluated */
+  nPromptEval: number
+  /** time spent sampling tokens */
+  sampleMs: number
+  /** number of sampled tokens */
+  nSample: number
+  /** time spent evaluating generated tokens */
+  evalMs: number
+  /** number of generated tokens evaluated */
+  nEval: number
+  /** wall time of the whole request */
+  totalMs: number
+}
 export interface InferenceResult {
   type: InferenceResultType
   data?: InferenceToken
+  progress?: InferenceProgress
+  /** why the generation ended, set on the End event */
+  finishReason?: FinishReason
+  /** performance timings of the request, set on the End event */
+  timings?: InferenceTimings
+  /** token counts of the request, set on the End event */
+  usage?: InferenceUsage
+  /** the full generated text, set on the End event */
+  text?: string
   message?: string
 }
+export interface EmbeddingProgress {
+  /** number of texts embedded so far */
+  nProcessed: number
+  /** number of texts in the batch */
+  nTotal: number
+}
+export const enum EmbeddingOverflow {
+  /** keep the first n_ctx tokens */
+  Truncate = 'Truncate',
+  /** embed n_ctx sized chunks, each starting with BOS, and average them */
+  ChunkMean = 'ChunkMean',
+  /** reject the input */
+  Error = 'Error'
+}
 export interface LogitBias {
   token: number
   bias: number
 }
 export interface Generate {
   nThreads: number
+  /**
+   * maximum number of tokens to generate
+   * Range: <= 0 to generate until the context is full
+   */
   nTokPredict: number
+  /**
+   * number of prompt tokens evaluated per llama_eval call
+   * Default: 512
+   */
+  nBatch?: number
   /**
    * logit bias for specific tokens
    * Default: None
@@ -103,15 +182,42 @@ export interface Generate {
    */
   mirostatEta?: number
   /**
-   * stop sequence
+   * stop sequence, matched on the generated text
    * Default: None
    */
   stopSequence?: string
+  /**
+   * stop sequences, generation stops at the first one found in the generated text
+   * Default: None
+   */
+  stopSequences?: Array<string>
   /**
    * consider newlines as a repeatable token
    * Default: true
    */
   penalizeNl?: boolean
+  /**
+   * number of prompt tokens to keep when the context is full and older tokens are dropped
+   * Default: 0
+   * -1 = keep the whole prompt
+   */
+  nKeep?: number
+  /**
+   * seed of the sampling rng for this request
+   * Default: None, continue from the current rng state
+   * < 0 = random seed
+   */
+  seed?: number
+  /**
+   * how embedding inputs longer than the context are handled
+   * Default: Truncate
+   */
+  embeddingOverflow?: EmbeddingOverflow
+  /**
+   * L2 normalize the embedding
+   * Default: false
+   */
+  normalizeEmbedding?: boolean
   /** prompt */
   prompt: string
 }
@@ -134,8 +240,46 @@ export interface LlamaLoraAdaptor {
   nThreads: number
 }
 export class LLama {
-  static load(params: Partial<ModelLoad>, enableLogger: boolean): Promise<LLama>
-  getWordEmbedding(params: Generate): Promise<Array<number>>
-  tokenize(params: string): Promise<Array<number>>
-  inference(params: Generate, callback: (result: InferenceResult) => void): () => void
+  /** Load a model, aborting `signal` rejects with an error whose code is `Cancelled`. */
+  static load(params: Partial<ModelLoad>, enableLogger: boolean, signal?: AbortSignal): Promise<LLama>
+  /** Apply a LoRA adapter to the loaded model, waits for the running inference to finish. */
+  applyLora(params: LlamaLoraAdaptor): Promise<void>
+  getWordEmbedding(params: Generate, signal?: AbortSignal): Promise<Array<number>>
+  /**
+   * Get the embeddings of a list of texts in one call, `params.prompt` is ignored.
+   * `progress` is called after each text is embedded.
+   */
+  getEmbeddings(texts: Array<string>, params: Generate, progress?: (progress: EmbeddingProgress) => void, signal?: AbortSignal): Promise<number[][]>
+  tokenize(params: string, signal?: AbortSignal): Promise<Array<number>>
+  /**
+   * Same as `inference`, but the results are pulled from the returned stream. At most
+   * `buffer_size` results are buffered, the generation waits until they are read. Aborting
+   * `signal`, or calling `abort` on the stream, stops the prompt evaluation or the generation.
+   */
+  inferenceStream(params: Generate, bufferSize?: number | undefined | null, signal?: AbortSignal): InferenceStream
+  /**
+   * Streaming the inference result to the callback. Aborting `signal`, or calling the returned
+   * function, stops the prompt evaluation or the generation.
+   */
+  inference(params: Generate, callback: (result: InferenceResult) => void, signal?: AbortSignal): () => void
+  /**
+   * Waits for the running calls to finish and frees the model right away, instead of when
+   * the object is garbage collected. Later calls reject, disposing twice is a no-op.
+   */
+  dispose(): Promise<void>
+}
+/** Inference results of `LLama.inferenceStream`, read with `next` until it resolves null. */
+export class InferenceStream {
+  /** Resolves the next result, or null once the End event has been read. */
+  next(): Promise<InferenceResult | null>
+  /** Stops the generation, the results produced so far can still be read. */
+  abort(): void
+}
+/** Tokenizer backed by a vocab_only context, the model weights are not loaded. */
+export class LLamaTokenizer {
+  static load(modelPath: string, enableLogger: boolean): Promise<LLamaTokenizer>
+  tokenize(text: string, addBos?: boolean | undefined | null): Array<number>
+  /** Converts tokens back to text, an id out of the vocabulary throws an `InvalidParam` error. */
+  detokenize(tokens: Array<number>): string
+  countTokens(text: string, addBos?: boolean | undefined | null): number
 }
@@ -252,7 +252,11 @@ if (!nativeBinding) {
   throw new Error(`Failed to load native binding`)
 }
 
-const { InferenceResultType, LLama } = nativeBinding
+const { InferenceResultType, FinishReason, EmbeddingOverflow, LLama, InferenceStream, LLamaTokenizer } = nativeBinding
 
 module.exports.InferenceResultType = InferenceResultType
+module.exports.FinishReason = FinishReason
+module.exports.EmbeddingOverflow = EmbeddingOverflow
 module.exports.LLama = LLama
+module.exports.InferenceStream = InferenceStream
+module.exports.LLamaTokenizer = LLamaTokenizer
@@ -1,21 +1,28 @@
-use std::{ffi::CStr, ptr::null_mut, slice};
+use std::{
+    ffi::{CStr, CString},
+    ptr::null,
+    slice,
+};
 
 use anyhow::Result;
+use common_rs::error::ErrorCode;
 use llama_sys::{
     llama_apply_lora_from_file, llama_context, llama_context_params, llama_eval, llama_free,
-    llama_get_embeddings, llama_get_logits, llama_init_from_file, llama_n_embd, llama_n_vocab,
-    llama_print_system_info, llama_sample_frequency_and_presence_penalties,
-    llama_sample_repetition_penalty, llama_sample_tail_free, llama_sample_temperature,
-    llama_sample_token, llama_sample_token_greedy, llama_sample_token_mirostat,
-    llama_sample_token_mirostat_v2, llama_sample_top_k, llama_sample_top_p, llama_sample_typical,
-    llama_token, llama_token_data, llama_token_data_array, llama_token_nl, llama_token_to_str,
+    llama_get_embeddings, llama_get_logits, llama_init_from_file, llama_model_quantize,
+    llama_n_embd, llama_n_vocab, llama_print_system_info,
+    llama_sample_frequency_and_presence_penalties, llama_sample_repetition_penalty,
+    llama_sample_tail_free, llama_sample_temperature, llama_sample_token,
+    llama_sample_token_greedy, llama_sample_token_mirostat, llama_sample_token_mirostat_v2,
+    llama_sample_top_k, llama_sample_top_p, llama_sample_typical, llama_set_rng_seed, llama_token,
+    llama_token_data, llama_token_data_array, llama_token_nl, llama_token_to_str,
 };
 
-use crate::types::{Generate, ModelLoad};
+use crate::types::{Generate, LlamaLoraAdaptor, ModelLoad, QuantizeType};
 
 // Represents the LLamaContext which wraps FFI calls to the llama.cpp library.
 pub struct LLamaContext {
     ctx: *mut llama_context,
+    vocab_only: bool,
 }
 
 impl LLamaContext {
@@ -23,41 +30,85 @@ impl LLamaContext {
     pub async fn from_file_and_params(params: &ModelLoad) -> Result<Self, napi::Error> {
         let lora_params = &params.lora;
         let context_params = ModelLoad::to_llama_context_params(params);
-        let ctx = unsafe {
-            llama_init_from_file(params.model_path.as_ptr() as *const i8, context_params)
-        };
+        let model_path = CString::new(params.model_path.as_str())
+            .map_err(|e| ErrorCode::InvalidParam.error(format!("Invalid model path: {}", e)))?;
+        let ctx = unsafe { llama_init_from_file(model_path.as_ptr(), context_params) };
 
         if ctx.is_null() {
-            return Err(napi::Error::from_reason(format!(
+            return Err(ErrorCode::ModelLoadFailed.error(format!(
                 "Failed to initialize LLama context from file: {}",
                 params.model_path,
             )));
         }
 
+        let context = Self {
+            ctx,
+            vocab_only: params.vocab_only,
+        };
+
         if let Some(lora_params) = lora_params {
-            let lora_base_path = lora_params
-                .lora_base
-                .as_ref()
-                .map(|p| p.as_ptr() as *const i8)
-                .unwrap_or(null_mut());
-
-            let err = unsafe {
-                llama_apply_lora_from_file(
-                    ctx,
-                    lora_params.lora_adapter.as_ptr() as *const i8,
-                    lora_base_path,
-                    lora_params.n_threads,
-                )
-            };
+            context.llama_apply_lora(lora_params)?;
+        }
+        Ok(context)
+    }
 
-            if err != 0 {
-                return Err(napi::Error::from_reason(format!(
-                    "Failed to apply lora adapter: {}",
-                    err
-                )));
-            }
+    // Applies a LoRA adapter on top of the loaded model, `lora_base` should point to an f16 model
+    // when the loaded model is quantized.
+    pub fn llama_apply_lora(&self, lora_params: &LlamaLoraAdaptor) -> Result<(), napi::Error> {
+        let lora_adapter = CString::new(lora_params.lora_adapter.as_str()).map_err(|e| {
+            ErrorCode::InvalidParam.error(format!("Invalid lora adapter path: {}", e))
+        })?;
+        let lora_base = lora_params
+            .lora_base
+            .as_ref()
+            .map(|p| CString::new(p.as_str()))
+            .transpose()
+            .map_err(|e| ErrorCode::InvalidParam.error(format!("Invalid lora base path: {}", e)))?;
+
+        let err = unsafe {
+            llama_apply_lora_from_file(
+                self.ctx,
+                lora_adapter.as_ptr(),
+                lora_base.as_ref().map(|p| p.as_ptr()).unwrap_or(null()),
+                lora_params.n_threads,
+            )
+        };
+
+        if err != 0 {
+            return Err(
+                ErrorCode::ModelLoadFailed.error(format!("Failed to apply lora adapter: {}", err))
+            );
         }
-        Ok(Self { ctx })
+        Ok(())
+    }
+
+    // Writes a quantized copy of the model at `input` to `output`, no context is needed for it.
+    pub fn llama_model_quantize(
+        input: &str,
+        output: &str,
+        quantize_type: QuantizeType,
+        n_threads: i32,
+    ) -> Result<(), napi::Error> {
+        let path = |path: &str| {
+            CString::new(path)
+                .map_err(|e| ErrorCode::InvalidParam.error(format!("Invalid model path: {}", e)))
+        };
+        let (input_path, output_path) = (path(input)?, path(output)?);
+
+        let err = unsafe {
+            llama_model_quantize(
+                input_path.as_ptr(),
+                output_path.as_ptr(),
+                quantize_type.into(),
+                n_threads,
+            )
+        };
+
+        if err != 0 {
+            return Err(ErrorCode::ModelLoadFailed
+                .error(format!("Failed to quantize model {}: {}", input, err)));
+        }
+        Ok(())
     }
 
     pub fn llama_print_system_info(&self) -> Result<()> {
@@ -69,6 +120,10 @@ impl LLamaContext {
         Ok(())
     }
 
+    pub fn llama_set_rng_seed(&self, seed: i32) {
+        unsafe { llama_set_rng_seed(self.ctx, seed) };
+    }
+
     // Executes the LLama sampling process with the specified configuration.
     pub fn llama_sample(
         &self,
@@ -215,17 +270,28 @@ impl LLamaContext {
         id
     }
 
-    pub fn llama_token_to_str(&self, token: &i32) -> Option<String> {
+    // llama.cpp indexes its vocabulary without a bounds check, token ids coming from the caller
+    // are checked first.
+    pub fn check_token(&self, token: llama_token) -> Result<(), napi::Error> {
+        let n_vocab = unsafe { llama_n_vocab(self.ctx) };
+        if !(0..n_vocab).contains(&token) {
+            return Err(ErrorCode::InvalidParam.error(format!(
+                "Token {} is out of the vocabulary of {} tokens",
+                token, n_vocab
+            )));
+        }
+        Ok(())
+    }
+
+    // Returns the raw bytes of the token, a multi-byte character may be split across tokens.
+    pub fn llama_token_to_bytes(&self, token: &i32) -> Option<Vec<u8>> {
+        self.check_token(*token).ok()?;
         let c_ptr = unsafe { llama_token_to_str(self.ctx, *token) };
         if c_ptr.is_null() {
             return None;
         }
 
-        let native_string = unsafe { CStr::from_ptr(c_ptr) }
-            .to_string_lossy()
-            .into_owned();
-
-        Some(native_string)
+        Some(unsafe { CStr::from_ptr(c_ptr) }.to_bytes().to_vec())
     }
 
     pub fn llama_get_embeddings(&self) -> Result<Vec<f32>, ()> {
@@ -247,13 +313,48 @@ impl LLamaContext {
         n_past: i32,
         input: &Generate,
     ) -> Result<(), napi::Error> {
+        if self.vocab_only {
+            return Err(ErrorCode::InvalidParam
+                .error("Cannot evaluate tokens, the model was loaded with vocab_only"));
+        }
+
         let res =
             unsafe { llama_eval(self.ctx, tokens.as_ptr(), n_tokens, n_past, input.n_threads) };
         if res == 0 {
             Ok(())
         } else {
-            Err(napi::Error::from_reason("LLama eval failed"))
+            Err(ErrorCode::InferenceFailed.error("LLama eval failed"))
+        }
+    }
+
+    // Evaluates the tokens in chunks of `n_batch` starting at `n_past`. `on_chunk` is called with the
+    // number of tokens evaluated so far after each chunk, returns false to stop early.
+    // Returns whether all tokens were evaluated.
+    pub fn llama_eval_batched(
+        &self,
+        tokens: &[llama_token],
+        n_past: i32,
+        input: &Generate,
+        mut on_chunk: impl FnMut(usize) -> bool,
+    ) -> Result<bool, napi::Error> {
+        let n_batch = std::cmp::max(input.n_batch.unwrap_or(512), 1) as usize;
+        let mut n_evaluated = 0;
+
+        for chunk in tokens.chunks(n_batch) {
+            self.llama_eval(
+                chunk,
+                chunk.len() as i32,
+                n_past + n_evaluated as i32,
+                input,
+            )?;
+            n_evaluated += chunk.len();
+
+            if !on_chunk(n_evaluated) {
+                return Ok(n_evaluated == tokens.len());
+            }
         }
+
+        Ok(true)
     }
 }
 
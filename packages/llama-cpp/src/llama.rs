@@ -1,27 +1,47 @@
-use std::sync::Arc;
+use std::time::Instant;
 
 use anyhow::Result;
-use tokio::sync::Mutex;
+use common_rs::{
+    abort::{aborted_error, AbortFlag},
+    error::ErrorCode,
+    output::{StopMatch, StopSequenceMatcher, Utf8Decoder},
+};
 
 use crate::{
     context::LLamaContext,
-    tokenizer::{llama_token_eos, tokenize},
-    types::{InferenceResult, InferenceResultType, InferenceToken, Generate, ModelLoad},
+    tokenizer::{llama_token_bos, llama_token_eos, tokenize},
+    types::{
+        EmbeddingOverflow, FinishReason, Generate, InferenceProgress, InferenceResult,
+        InferenceResultType, InferenceTimings, InferenceToken, InferenceUsage, LlamaLoraAdaptor,
+        ModelLoad, QuantizeType,
+    },
 };
 
 pub struct LLamaInternal {
     context: LLamaContext,
     context_params: ModelLoad,
+    load_ms: f64,
+}
+
+fn elapsed_ms(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.0
 }
 
 impl LLamaInternal {
     pub async fn load(
         params: ModelLoad,
         enable_logger: bool,
-    ) -> Result<Arc<Mutex<LLamaInternal>>, napi::Error> {
+        abort: &AbortFlag,
+    ) -> Result<LLamaInternal, napi::Error> {
+        abort.check()?;
+        let start = Instant::now();
+        let context = LLamaContext::from_file_and_params(&params).await?;
+        // llama.cpp cannot stop a load half way, the model is freed if the caller gave up meanwhile.
+        abort.check()?;
         let llama = LLamaInternal {
-            context: LLamaContext::from_file_and_params(&params).await?,
+            context,
             context_params: params,
+            load_ms: elapsed_ms(start),
         };
 
         if enable_logger {
@@ -30,145 +50,354 @@ impl LLamaInternal {
             })?;
         }
 
-        Ok(Arc::new(Mutex::new(llama)))
+        Ok(llama)
+    }
+
+    /// Quantizes the model file at `input` into `output`, the model does not need to be loaded.
+    pub fn quantize(
+        input: &str,
+        output: &str,
+        quantize_type: QuantizeType,
+        n_threads: i32,
+    ) -> Result<(), napi::Error> {
+        let start = Instant::now();
+        LLamaContext::llama_model_quantize(input, output, quantize_type, n_threads)?;
+        log::info!("quantized {} in {:.2}ms", input, elapsed_ms(start));
+        Ok(())
+    }
+
+    pub fn apply_lora(&self, params: &LlamaLoraAdaptor) -> Result<(), napi::Error> {
+        let start = Instant::now();
+        self.context.llama_apply_lora(params)?;
+        log::info!(
+            "applied lora {} in {:.2}ms",
+            params.lora_adapter,
+            elapsed_ms(start)
+        );
+        Ok(())
     }
+
     pub async fn tokenize(&self, input: &str) -> Result<Vec<i32>, napi::Error> {
         let context = &self.context;
-        Ok(tokenize(context, input, false))
+        tokenize(context, input, false)
     }
 
-    pub async fn embedding(&self, input: &Generate) -> Result<Vec<f64>, napi::Error> {
-        let context = &self.context;
-        let embd_inp = tokenize(context, input.prompt.as_str(), true);
+    pub fn embedding(&self, input: &Generate, abort: &AbortFlag) -> Result<Vec<f64>, napi::Error> {
+        self.embed(input.prompt.as_str(), input, abort)
+    }
 
-        // let end_text = "\n";
-        // let end_token =
-        //     tokenize(input_ctx, end_text, context_params_c.n_ctx as usize, false).unwrap();
+    // Embeds every text on the loaded context, `input.prompt` is ignored.
+    pub fn embeddings(
+        &self,
+        texts: &[String],
+        input: &Generate,
+        abort: &AbortFlag,
+        mut on_progress: impl FnMut(usize),
+    ) -> Result<Vec<Vec<f64>>, napi::Error> {
+        let mut embeddings = Vec::with_capacity(texts.len());
 
-        context
-            .llama_eval(embd_inp.as_slice(), embd_inp.len() as i32, 0, input)
-            .map_err(|e| napi::Error::from_reason(format!("Failed to evaluate input: {:?}", e)))?;
+        for text in texts.iter() {
+            embeddings.push(self.embed(text, input, abort)?);
+            on_progress(embeddings.len());
+        }
 
-        let embeddings = context.llama_get_embeddings();
+        Ok(embeddings)
+    }
 
-        if let Ok(embeddings) = embeddings {
-            Ok(embeddings.iter().map(|&x| x as f64).collect())
+    fn embed(
+        &self,
+        prompt: &str,
+        input: &Generate,
+        abort: &AbortFlag,
+    ) -> Result<Vec<f64>, napi::Error> {
+        let context = &self.context;
+
+        if !self.context_params.embedding {
+            return Err(ErrorCode::InvalidParam
+                .error("Embeddings are not enabled, load the model with embedding: true"));
+        }
+
+        // Every chunk starts with BOS, so each one is embedded like a whole input would be.
+        let text_tokens = tokenize(context, prompt, false)?;
+        let n_ctx = self.context_params.n_ctx as usize;
+        let n_chunk = n_ctx - 1;
+        let token_bos = llama_token_bos();
+        let with_bos = |chunk: &[i32]| [&[token_bos], chunk].concat();
+
+        // Inputs longer than the context are truncated, or split into chunks whose embeddings are averaged.
+        let chunks: Vec<Vec<i32>> = if text_tokens.len() <= n_chunk {
+            vec![with_bos(&text_tokens)]
         } else {
-            Err(napi::Error::from_reason("Failed to get embeddings"))
+            match input
+                .embedding_overflow
+                .unwrap_or(EmbeddingOverflow::Truncate)
+            {
+                EmbeddingOverflow::Truncate => vec![with_bos(&text_tokens[..n_chunk])],
+                EmbeddingOverflow::ChunkMean => text_tokens.chunks(n_chunk).map(with_bos).collect(),
+                EmbeddingOverflow::Error => {
+                    return Err(ErrorCode::ContextFull.error(format!(
+                        "Input is too long ({} tokens, context size is {})",
+                        text_tokens.len() + 1,
+                        n_ctx
+                    )))
+                }
+            }
+        };
+
+        let mut embedding: Vec<f64> = Vec::new();
+
+        for chunk in chunks.iter() {
+            let is_evaluated = context
+                .llama_eval_batched(chunk, 0, input, |_| !abort.is_aborted())
+                .map_err(|e| {
+                    ErrorCode::InferenceFailed.error(format!("Failed to evaluate input: {}", e))
+                })?;
+
+            if !is_evaluated {
+                return Err(aborted_error());
+            }
+
+            let chunk_embedding = context
+                .llama_get_embeddings()
+                .map_err(|_| ErrorCode::InferenceFailed.error("Failed to get embeddings"))?;
+
+            if embedding.is_empty() {
+                embedding = vec![0.0; chunk_embedding.len()];
+            }
+            for (sum, x) in embedding.iter_mut().zip(chunk_embedding.iter()) {
+                *sum += *x as f64 / chunks.len() as f64;
+            }
+        }
+
+        if input.normalize_embedding.unwrap_or(false) {
+            let norm = embedding.iter().map(|x| x * x).sum::<f64>().sqrt();
+            if norm > 0.0 {
+                embedding.iter_mut().for_each(|x| *x /= norm);
+            }
         }
+
+        Ok(embedding)
     }
 
     pub fn inference(
         &self,
         input: &Generate,
-        running: Arc<Mutex<bool>>,
+        abort: &AbortFlag,
         callback: impl Fn(InferenceResult),
     ) -> Result<(), napi::Error> {
+        let start = Instant::now();
+        let mut timings = InferenceTimings {
+            load_ms: self.load_ms,
+            ..Default::default()
+        };
+
         let context = &self.context;
+        for logit_bias in input.logit_bias.iter().flatten() {
+            context.check_token(logit_bias.token)?;
+        }
+
         let context_params_c = ModelLoad::to_llama_context_params(&self.context_params);
-        // Tokenize the stop sequence and input prompt.
-        let tokenized_stop_prompt = input
-            .stop_sequence
-            .as_ref()
-            .map(|stop_sequence| tokenize(context, stop_sequence, false));
+        // Stop sequences are matched on the generated text.
+        let mut stop_sequences = input.stop_sequences.clone().unwrap_or_default();
+        if let Some(stop_sequence) = &input.stop_sequence {
+            stop_sequences.push(stop_sequence.clone());
+        }
+        let mut stop_matcher = StopSequenceMatcher::new(stop_sequences);
+        let mut decoder = Utf8Decoder::default();
+
+        // Everything sent to the caller, reported with the End event.
+        let mut text = String::new();
+        let mut send_token = |token: String| {
+            if !token.is_empty() {
+                text.push_str(&token);
+                callback(InferenceResult {
+                    r#type: InferenceResultType::Data,
+                    data: Some(InferenceToken { token }),
+                    progress: None,
+                    finish_reason: None,
+                    timings: None,
+                    usage: None,
+                    text: None,
+                    message: None,
+                });
+            }
+        };
+
+        let tokenized_input = tokenize(context, input.prompt.as_str(), true)?;
+        let n_ctx = context_params_c.n_ctx;
+        let mut usage = InferenceUsage {
+            n_prompt_tokens: tokenized_input.len() as i32,
+            n_generated_tokens: 0,
+        };
 
-        log::info!("tokenized_stop_prompt: {:?}", tokenized_stop_prompt);
+        if tokenized_input.len() as i32 > n_ctx - 4 {
+            let mut result = end_result(FinishReason::ContextFull, timings, usage, String::new());
+            result.message = Some(format!(
+                "Prompt is too long ({} tokens, context size is {})",
+                tokenized_input.len(),
+                n_ctx
+            ));
+            callback(result);
+            return Ok(());
+        }
 
-        let tokenized_input = tokenize(context, input.prompt.as_str(), true);
+        // The seed is set before the prompt is evaluated, so the RNG state does not depend on it.
+        if let Some(seed) = input.seed {
+            context.llama_set_rng_seed(seed);
+        }
+
+        // Number of prompt tokens kept at the start of the context when it overflows.
+        let n_keep = match input.n_keep {
+            Some(n_keep) if n_keep >= 0 => std::cmp::min(n_keep as usize, tokenized_input.len()),
+            Some(_) => tokenized_input.len(),
+            None => 0,
+        };
 
-        // Embd contains the prompt and the completion. The longer the prompt, the shorter the completion.
+        // Embd contains the prompt and the completion.
         let mut embd = tokenized_input.clone();
-        embd.resize(context_params_c.n_ctx as usize, 0);
-
-        // Feed prompt to the model.
-        context.llama_eval(
-            tokenized_input.as_slice(),
-            tokenized_input.len() as i32,
-            0,
-            input,
-        )?;
+
+        // Feed prompt to the model, reporting progress and checking for abort between batches.
+        let n_total = tokenized_input.len() as i32;
+        let prompt_start = Instant::now();
+        let is_prompt_evaluated =
+            context.llama_eval_batched(tokenized_input.as_slice(), 0, input, |n_evaluated| {
+                timings.n_prompt_eval = n_evaluated as i32;
+                callback(InferenceResult {
+                    r#type: InferenceResultType::Progress,
+                    data: None,
+                    progress: Some(InferenceProgress {
+                        n_evaluated: n_evaluated as i32,
+                        n_total,
+                    }),
+                    finish_reason: None,
+                    timings: None,
+                    usage: None,
+                    text: None,
+                    message: None,
+                });
+                !abort.is_aborted()
+            })?;
+
+        timings.prompt_eval_ms = elapsed_ms(prompt_start);
+
+        if !is_prompt_evaluated {
+            timings.total_ms = elapsed_ms(start);
+            callback(end_result(
+                FinishReason::Aborted,
+                timings,
+                usage,
+                String::new(),
+            ));
+            return Ok(());
+        }
+
         let token_eos = llama_token_eos();
 
         // Generate remaining tokens.
-        let mut n_remaining = context_params_c.n_ctx - tokenized_input.len() as i32;
-        let mut n_used = tokenized_input.len() - 1;
-        let mut stop_sequence_i = 0;
-        let mut completed = false;
+        let mut n_past = tokenized_input.len();
+        let mut n_generated = 0;
+        let finish_reason;
 
-        while n_remaining > 0 {
+        // Without a positive limit the generation stops once it would fill the context, the context
+        // swapping below would keep it going forever otherwise.
+        let n_tok_predict = if input.n_tok_predict > 0 {
+            input.n_tok_predict as usize
+        } else {
+            n_ctx as usize - tokenized_input.len()
+        };
+
+        loop {
             // Check if we are aborted by caller.
-            let running = *running.blocking_lock();
-            if !running {
+            if abort.is_aborted() {
+                finish_reason = FinishReason::Aborted;
                 break;
             }
 
-            n_used += 1;
-            n_remaining -= 1;
-
+            let sample_start = Instant::now();
             let tok = context.llama_sample(embd.as_mut_slice(), input, &context_params_c);
-            embd[n_used] = tok;
+            timings.sample_ms += elapsed_ms(sample_start);
+            timings.n_sample += 1;
+            embd.push(tok);
+            n_generated += 1;
 
             if tok == token_eos {
-                completed = true;
+                finish_reason = FinishReason::Eos;
                 break;
             }
 
-            // If we are predicting a fixed number of tokens, check if we have reached that number.
-            if input.n_tok_predict != 0
-                && n_used > (input.n_tok_predict as usize) + tokenized_input.len() - 1
-            {
-                return Err(napi::Error::from_reason("Too many tokens predicted"));
-            }
-
-            // Check if we have reached the stop sequence.
-            if let Some(tokenized_stop_prompt) = &tokenized_stop_prompt {
-                if tok == tokenized_stop_prompt[stop_sequence_i] {
-                    stop_sequence_i += 1;
-                    if stop_sequence_i >= tokenized_stop_prompt.len() {
-                        completed = true;
+            // We can output the token, unless it completes or may start a stop sequence.
+            if let Some(bytes) = context.llama_token_to_bytes(&tok) {
+                match stop_matcher.push(&decoder.push(&bytes)) {
+                    StopMatch::Continue(output) => send_token(output),
+                    StopMatch::Stop(output) => {
+                        send_token(output);
+                        decoder.flush();
+                        finish_reason = FinishReason::StopSequence;
                         break;
                     }
-                } else {
-                    stop_sequence_i = 0;
                 }
             }
 
-            // We can output the token.
-            let output = context.llama_token_to_str(&embd[n_used]);
-            if let Some(output) = output {
-                if stop_sequence_i == 0 {
-                    callback(InferenceResult {
-                        r#type: InferenceResultType::Data,
-                        data: Some(InferenceToken {
-                            token: output,
-                            completed: false,
-                        }),
-                        message: None,
-                    });
-                }
+            // Check if we have reached the number of tokens to predict, before the last token is
+            // evaluated for nothing.
+            if n_generated >= n_tok_predict {
+                finish_reason = FinishReason::Length;
+                break;
+            }
+
+            // The context is full: keep the first n_keep tokens and re-evaluate the most recent
+            // half of the rest, like llama.cpp's main example does.
+            if n_past + 1 > n_ctx as usize {
+                let n_left = n_past - n_keep;
+                let recent = &embd[embd.len() - 1 - n_left / 2..embd.len() - 1];
+
+                log::info!("context is full, swapping {} tokens", recent.len());
+
+                let swap_start = Instant::now();
+                n_past = n_keep;
+                context.llama_eval_batched(recent, n_past as i32, input, |_| true)?;
+                n_past += recent.len();
+                timings.prompt_eval_ms += elapsed_ms(swap_start);
+                timings.n_prompt_eval += recent.len() as i32;
             }
 
             // Continue feeding the token to the model.
-            context.llama_eval(&embd[n_used..], 1, n_used as i32, input)?;
+            let eval_start = Instant::now();
+            context.llama_eval(&[tok], 1, n_past as i32, input)?;
+            timings.eval_ms += elapsed_ms(eval_start);
+            timings.n_eval += 1;
+            n_past += 1;
         }
 
-        if completed {
-            callback(InferenceResult {
-                r#type: InferenceResultType::Data,
-                data: Some(InferenceToken {
-                    token: "\n\n<end>\n".to_string(),
-                    completed: true,
-                }),
-                message: None,
-            });
-        }
+        // Release the text held back for a stop sequence that never completed, and any
+        // incomplete character left at the end.
+        send_token(stop_matcher.flush());
+        send_token(decoder.flush());
 
-        callback(InferenceResult {
-            r#type: InferenceResultType::End,
-            data: None,
-            message: None,
-        });
+        timings.total_ms = elapsed_ms(start);
+        usage.n_generated_tokens = n_generated as i32;
+        log::info!("{:?}", timings);
+
+        callback(end_result(finish_reason, timings, usage, text));
 
         Ok(())
     }
 }
+
+fn end_result(
+    finish_reason: FinishReason,
+    timings: InferenceTimings,
+    usage: InferenceUsage,
+    text: String,
+) -> InferenceResult {
+    InferenceResult {
+        r#type: InferenceResultType::End,
+        data: None,
+        progress: None,
+        finish_reason: Some(finish_reason),
+        timings: Some(timings),
+        usage: Some(usage),
+        text: Some(text),
+        message: None,
+    }
+}
@@ -0,0 +1,304 @@
+use std::sync::Arc;
+
+use common_rs::{
+    abort::AbortFlag,
+    callback::Callback,
+    error::{parse_params, ErrorCode},
+    handle::ModelHandle,
+    logger::LLamaLogger,
+    stream::{result_stream, run_inference, InferenceEvent, ResultStream},
+};
+use napi::{bindgen_prelude::*, JsFunction, JsObject};
+
+use crate::{
+    context::LLamaContext,
+    llama::LLamaInternal,
+    tokenizer,
+    types::{
+        EmbeddingProgress, FinishReason, Generate, InferenceResult, InferenceResultType,
+        LlamaLoraAdaptor, ModelLoad,
+    },
+};
+
+#[napi]
+pub struct LLama {
+    llama: ModelHandle<LLamaInternal>,
+}
+
+#[napi]
+impl LLama {
+    /// Load a model, aborting `signal` rejects with an error whose code is `Cancelled`.
+    #[napi(ts_return_type = "Promise<LLama>")]
+    pub fn load(
+        env: Env,
+        #[napi(ts_arg_type = "Partial<ModelLoad>")] params: serde_json::Value,
+        enable_logger: bool,
+        #[napi(ts_arg_type = "AbortSignal")] signal: Option<JsObject>,
+    ) -> Result<JsObject> {
+        let params = parse_params::<ModelLoad>(params)?;
+        let abort = AbortFlag::from_signal(&env, signal)?;
+
+        let logger = LLamaLogger::get_singleton();
+        logger.set_enabled(enable_logger);
+
+        env.execute_tokio_future(
+            async move { LLamaInternal::load(params, enable_logger, &abort).await },
+            |_, llama| {
+                Ok(LLama {
+                    llama: ModelHandle::new(llama),
+                })
+            },
+        )
+    }
+
+    /// Apply a LoRA adapter to the loaded model, waits for the running inference to finish.
+    #[napi]
+    pub async fn apply_lora(&self, params: LlamaLoraAdaptor) -> Result<()> {
+        let llama = self.llama.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let llama = llama.blocking_write()?;
+            llama.apply_lora(&params)
+        })
+        .await
+        .map_err(|e| ErrorCode::InferenceFailed.error(format!("Failed to apply lora: {}", e)))?
+    }
+
+    #[napi(ts_return_type = "Promise<Array<number>>")]
+    pub fn get_word_embedding(
+        &self,
+        env: Env,
+        params: Generate,
+        #[napi(ts_arg_type = "AbortSignal")] signal: Option<JsObject>,
+    ) -> Result<JsObject> {
+        let abort = AbortFlag::from_signal(&env, signal)?;
+        let llama = self.llama.clone();
+
+        env.execute_tokio_future(
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    llama.blocking_write()?.embedding(&params, &abort)
+                })
+                .await
+                .map_err(|e| {
+                    ErrorCode::InferenceFailed.error(format!("Failed to run embedding: {}", e))
+                })?
+            },
+            |_, embedding| Ok(embedding),
+        )
+    }
+
+    /// Get the embeddings of a list of texts in one call, `params.prompt` is ignored.
+    /// `progress` is called after each text is embedded.
+    #[napi(ts_return_type = "Promise<number[][]>")]
+    pub fn get_embeddings(
+        &self,
+        env: Env,
+        texts: Vec<String>,
+        params: Generate,
+        #[napi(ts_arg_type = "(progress: EmbeddingProgress) => void")] progress: Option<JsFunction>,
+        #[napi(ts_arg_type = "AbortSignal")] signal: Option<JsObject>,
+    ) -> Result<JsObject> {
+        let abort = AbortFlag::from_signal(&env, signal)?;
+        let progress = progress.as_ref().map(Callback::new).transpose()?;
+
+        let llama = self.llama.clone();
+        let n_total = texts.len() as u32;
+
+        env.execute_tokio_future(
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    let llama = llama.blocking_write()?;
+                    llama.embeddings(&texts, &params, &abort, |n_processed| {
+                        if let Some(progress) = &progress {
+                            progress.call(EmbeddingProgress {
+                                n_processed: n_processed as u32,
+                                n_total,
+                            });
+                        }
+                    })
+                })
+                .await
+                .map_err(|e| {
+                    ErrorCode::InferenceFailed.error(format!("Failed to run embedding: {}", e))
+                })?
+            },
+            |_, embeddings| Ok(embeddings),
+        )
+    }
+
+    #[napi(ts_return_type = "Promise<Array<number>>")]
+    pub fn tokenize(
+        &self,
+        env: Env,
+        params: String,
+        #[napi(ts_arg_type = "AbortSignal")] signal: Option<JsObject>,
+    ) -> Result<JsObject> {
+        let abort = AbortFlag::from_signal(&env, signal)?;
+        let llama = self.llama.clone();
+
+        env.execute_tokio_future(
+            async move {
+                let llama = llama.write().await?;
+                abort.check()?;
+                llama.tokenize(&params).await
+            },
+            |_, tokens| Ok(tokens),
+        )
+    }
+
+    /// Same as `inference`, but the results are pulled from the returned stream. At most
+    /// `buffer_size` results are buffered, the generation waits until they are read. Aborting
+    /// `signal`, or calling `abort` on the stream, stops the prompt evaluation or the generation.
+    #[napi]
+    pub fn inference_stream(
+        &self,
+        env: Env,
+        params: Generate,
+        buffer_size: Option<u32>,
+        #[napi(ts_arg_type = "AbortSignal")] signal: Option<JsObject>,
+    ) -> Result<InferenceStream> {
+        let abort = AbortFlag::from_signal(&env, signal)?;
+        let (sender, stream) = result_stream(buffer_size, abort.clone());
+        let llama = self.llama.clone();
+
+        tokio::task::spawn_blocking(move || {
+            run_inference(
+                |result| sender.blocking_send(result),
+                |send| llama.blocking_write()?.inference(&params, &abort, send),
+            );
+        });
+
+        Ok(InferenceStream { stream })
+    }
+
+    /// Streaming the inference result to the callback. Aborting `signal`, or calling the returned
+    /// function, stops the prompt evaluation or the generation.
+    #[napi(ts_return_type = "() => void")]
+    pub fn inference(
+        &self,
+        env: Env,
+        params: Generate,
+        #[napi(ts_arg_type = "(result: InferenceResult) => void")] callback: JsFunction,
+        #[napi(ts_arg_type = "AbortSignal")] signal: Option<JsObject>,
+    ) -> Result<JsFunction> {
+        let callback = Callback::new(&callback)?;
+        let abort = AbortFlag::from_signal(&env, signal)?;
+        let llama = self.llama.clone();
+
+        {
+            let abort = abort.clone();
+            tokio::task::spawn_blocking(move || {
+                run_inference(
+                    |result| callback.call(result),
+                    |send| llama.blocking_write()?.inference(&params, &abort, send),
+                );
+            });
+        }
+
+        // The returned function may outlive the task, it should not keep the listener on `signal`.
+        let abort = abort.detached();
+        env.create_function_from_closure("abort_inference", move |_| {
+            abort.abort();
+            Ok(())
+        })
+    }
+
+    /// Waits for the running calls to finish and frees the model right away, instead of when
+    /// the object is garbage collected. Later calls reject, disposing twice is a no-op.
+    #[napi]
+    pub async fn dispose(&self) -> Result<()> {
+        self.llama.dispose().await;
+        Ok(())
+    }
+}
+
+impl InferenceEvent for InferenceResult {
+    fn error(message: String) -> Self {
+        InferenceResult {
+            r#type: InferenceResultType::Error,
+            data: None,
+            progress: None,
+            finish_reason: None,
+            timings: None,
+            usage: None,
+            text: None,
+            message: Some(message),
+        }
+    }
+
+    fn failed() -> Self {
+        InferenceResult {
+            r#type: InferenceResultType::End,
+            data: None,
+            progress: None,
+            finish_reason: Some(FinishReason::Error),
+            timings: None,
+            usage: None,
+            text: None,
+            message: None,
+        }
+    }
+}
+
+/// Inference results of `LLama.inferenceStream`, read with `next` until it resolves null.
+#[napi]
+pub struct InferenceStream {
+    stream: ResultStream<InferenceResult>,
+}
+
+#[napi]
+impl InferenceStream {
+    /// Resolves the next result, or null once the End event has been read.
+    #[napi]
+    pub async fn next(&self) -> Result<Option<InferenceResult>> {
+        Ok(self.stream.next().await)
+    }
+
+    /// Stops the generation, the results produced so far can still be read.
+    #[napi]
+    pub fn abort(&self) {
+        self.stream.abort();
+    }
+}
+
+/// Tokenizer backed by a vocab_only context, the model weights are not loaded.
+#[napi]
+pub struct LLamaTokenizer {
+    context: Arc<LLamaContext>,
+}
+
+#[napi]
+impl LLamaTokenizer {
+    #[napi]
+    pub async fn load(model_path: String, enable_logger: bool) -> Result<LLamaTokenizer> {
+        let logger = LLamaLogger::get_singleton();
+        logger.set_enabled(enable_logger);
+
+        let params = ModelLoad {
+            model_path,
+            vocab_only: true,
+            ..Default::default()
+        };
+
+        Ok(Self {
+            context: Arc::new(LLamaContext::from_file_and_params(&params).await?),
+        })
+    }
+
+    #[napi]
+    pub fn tokenize(&self, text: String, add_bos: Option<bool>) -> Result<Vec<i32>> {
+        tokenizer::tokenize(&self.context, &text, add_bos.unwrap_or(false))
+    }
+
+    /// Converts tokens back to text, an id out of the vocabulary throws an `InvalidParam` error.
+    #[napi]
+    pub fn detokenize(&self, tokens: Vec<i32>) -> Result<String> {
+        tokenizer::detokenize(&self.context, &tokens)
+    }
+
+    #[napi]
+    pub fn count_tokens(&self, text: String, add_bos: Option<bool>) -> Result<u32> {
+        Ok(self.tokenize(text, add_bos)?.len() as u32)
+    }
+}
@@ -3,35 +3,68 @@
 use std::ffi::CString;
 use std::os::raw::c_char;
 
-use llama_sys::{llama_token, llama_token_eos as inner_eos, llama_tokenize};
+use common_rs::error::ErrorCode;
+use llama_sys::{
+    llama_token, llama_token_bos as inner_bos, llama_token_eos as inner_eos, llama_tokenize,
+};
 
 use crate::context::LLamaContext;
 
-// Helper function to convert a Rust string to a C string.
-fn to_cstring(s: &str) -> CString {
-    CString::new(s).expect("CString::new failed")
+// Helper function to convert a Rust string to a C string, the text cannot contain NUL bytes.
+fn to_cstring(s: &str) -> Result<CString, napi::Error> {
+    CString::new(s).map_err(|e| ErrorCode::InvalidParam.error(format!("Invalid text: {}", e)))
 }
 
 pub fn llama_token_eos() -> i32 {
     unsafe { inner_eos() }
 }
 
-/// Tokenizes the given text using the provided LLamaContext, respecting the context_window_size and add_bos options.
+pub fn llama_token_bos() -> i32 {
+    unsafe { inner_bos() }
+}
+
+/// Tokenizes the given text using the provided LLamaContext, the result is not limited to the
+/// context size.
 ///
 /// # Arguments
 ///
 /// * `context` - A reference to the LLamaContext used for tokenization.
 /// * `text` - The text to tokenize.
-/// * `context_window_size` - The maximum allowed size of the tokenized input.
 /// * `add_bos` - Whether to add the beginning-of-sentence token.
 ///
 /// # Returns
 ///
-/// A Result containing a Vec of llama_tokens on success, or an error if the tokenized input is too long.
-pub(crate) fn tokenize(context: &LLamaContext, text: &str, add_bos: bool) -> Vec<llama_token> {
+/// A Result containing a Vec of llama_tokens on success, or an error if the text contains a NUL byte.
+pub(crate) fn tokenize(
+    context: &LLamaContext,
+    text: &str,
+    add_bos: bool,
+) -> Result<Vec<llama_token>, napi::Error> {
     llama_tokenize_helper(context, text, add_bos)
 }
 
+/// Converts tokens back to text, invalid UTF-8 is replaced with replacement characters.
+///
+/// # Arguments
+///
+/// * `context` - A reference to the LLamaContext used for tokenization.
+/// * `tokens` - The tokens to convert.
+///
+/// # Returns
+///
+/// The text of the tokens, or an error if a token is out of the vocabulary.
+pub(crate) fn detokenize(
+    context: &LLamaContext,
+    tokens: &[llama_token],
+) -> Result<String, napi::Error> {
+    let mut bytes = Vec::new();
+    for token in tokens {
+        context.check_token(*token)?;
+        bytes.extend(context.llama_token_to_bytes(token).unwrap_or_default());
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
 /// Helper function to tokenize text using the provided LLamaContext and add_bos option.
 ///
 /// # Arguments
@@ -42,21 +75,34 @@ pub(crate) fn tokenize(context: &LLamaContext, text: &str, add_bos: bool) -> Vec
 ///
 /// # Returns
 ///
-/// A Vec of llama_tokens representing the tokenized input.
-fn llama_tokenize_helper(context: &LLamaContext, text: &str, add_bos: bool) -> Vec<llama_token> {
-    let mut res = Vec::with_capacity(text.len() + add_bos as usize);
-    let c_text = to_cstring(text);
-
-    let n = unsafe {
-        llama_tokenize(
-            **context,
-            c_text.as_ptr() as *const c_char,
-            res.as_mut_ptr(),
-            res.capacity() as i32,
-            add_bos,
-        )
-    };
-    assert!(n >= 0);
-    unsafe { res.set_len(n as usize) };
-    res
+/// A Result containing a Vec of llama_tokens representing the tokenized input.
+fn llama_tokenize_helper(
+    context: &LLamaContext,
+    text: &str,
+    add_bos: bool,
+) -> Result<Vec<llama_token>, napi::Error> {
+    let c_text = to_cstring(text)?;
+    let mut capacity = text.len() + add_bos as usize;
+
+    loop {
+        let mut res = Vec::with_capacity(capacity);
+        let n = unsafe {
+            llama_tokenize(
+                **context,
+                c_text.as_ptr() as *const c_char,
+                res.as_mut_ptr(),
+                res.capacity() as i32,
+                add_bos,
+            )
+        };
+
+        // A negative result is the number of tokens needed, retry with a large enough buffer.
+        if n < 0 {
+            capacity = n.unsigned_abs() as usize;
+            continue;
+        }
+
+        unsafe { res.set_len(n as usize) };
+        return Ok(res);
+    }
 }
@@ -1,4 +1,9 @@
-use llama_sys::llama_context_params;
+use llama_sys::{
+    llama_context_params, llama_ftype, llama_ftype_LLAMA_FTYPE_MOSTLY_F16,
+    llama_ftype_LLAMA_FTYPE_MOSTLY_Q4_0, llama_ftype_LLAMA_FTYPE_MOSTLY_Q4_1,
+    llama_ftype_LLAMA_FTYPE_MOSTLY_Q5_0, llama_ftype_LLAMA_FTYPE_MOSTLY_Q5_1,
+    llama_ftype_LLAMA_FTYPE_MOSTLY_Q8_0,
+};
 use napi::bindgen_prelude::*;
 use serde::{Deserialize, Serialize};
 
@@ -6,23 +11,110 @@ use serde::{Deserialize, Serialize};
 #[derive(Clone, Debug)]
 pub struct InferenceToken {
     pub token: String,
-    pub completed: bool,
+}
+
+#[napi(object)]
+#[derive(Clone, Debug)]
+pub struct InferenceProgress {
+    /// number of prompt tokens evaluated so far
+    pub n_evaluated: i32,
+    /// number of prompt tokens to evaluate
+    pub n_total: i32,
 }
 
 #[napi(string_enum)]
 pub enum InferenceResultType {
     Error,
     Data,
+    Progress,
     End,
 }
 
+#[napi(string_enum)]
+#[derive(Debug)]
+pub enum FinishReason {
+    /// the model generated the end of sequence token
+    Eos,
+    /// the stop sequence was generated
+    StopSequence,
+    /// n_tok_predict tokens were generated
+    Length,
+    /// the prompt does not fit in the context
+    ContextFull,
+    /// the inference was aborted by the caller
+    Aborted,
+    /// the inference failed, see the Error event
+    Error,
+}
+
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct InferenceUsage {
+    /// number of tokens in the prompt
+    pub n_prompt_tokens: i32,
+    /// number of tokens generated
+    pub n_generated_tokens: i32,
+}
+
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct InferenceTimings {
+    /// time the model took to load, in milliseconds. It was loaded once before the first request,
+    /// so this is the same on every request and not included in `total_ms`
+    pub load_ms: f64,
+    /// time spent evaluating the prompt, including re-evaluation after context swaps
+    pub prompt_eval_ms: f64,
+    /// number of prompt tokens evaluated
+    pub n_prompt_eval: i32,
+    /// time spent sampling tokens
+    pub sample_ms: f64,
+    /// number of sampled tokens
+    pub n_sample: i32,
+    /// time spent evaluating generated tokens
+    pub eval_ms: f64,
+    /// number of generated tokens evaluated
+    pub n_eval: i32,
+    /// wall time of the whole request
+    pub total_ms: f64,
+}
+
 #[napi(object)]
 pub struct InferenceResult {
     pub r#type: InferenceResultType,
     pub data: Option<InferenceToken>,
+    pub progress: Option<InferenceProgress>,
+    /// why the generation ended, set on the End event
+    pub finish_reason: Option<FinishReason>,
+    /// performance timings of the request, set on the End event
+    pub timings: Option<InferenceTimings>,
+    /// token counts of the request, set on the End event
+    pub usage: Option<InferenceUsage>,
+    /// the full generated text, set on the End event
+    pub text: Option<String>,
     pub message: Option<String>,
 }
 
+#[napi(object)]
+pub struct EmbeddingProgress {
+    /// number of texts embedded so far
+    pub n_processed: u32,
+    /// number of texts in the batch
+    pub n_total: u32,
+}
+
+#[napi(string_enum)]
+// napi derives Copy and Clone for enums, but not with the `noop` feature.
+#[cfg_attr(feature = "noop", derive(Clone, Copy))]
+#[derive(Debug)]
+pub enum EmbeddingOverflow {
+    /// keep the first n_ctx tokens
+    Truncate,
+    /// embed n_ctx sized chunks, each starting with BOS, and average them
+    ChunkMean,
+    /// reject the input
+    Error,
+}
+
 #[napi(object)]
 #[derive(Debug, Clone)]
 pub struct LogitBias {
@@ -31,11 +123,18 @@ pub struct LogitBias {
 }
 
 #[napi(object)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Generate {
     pub n_threads: i32,
+
+    /// maximum number of tokens to generate
+    /// Range: <= 0 to generate until the context is full
     pub n_tok_predict: i32,
 
+    /// number of prompt tokens evaluated per llama_eval call
+    /// Default: 512
+    pub n_batch: Option<i32>,
+
     /// logit bias for specific tokens
     /// Default: None
     pub logit_bias: Option<Vec<LogitBias>>,
@@ -101,14 +200,36 @@ pub struct Generate {
     /// Default: 0.1
     pub mirostat_eta: Option<f64>,
 
-    /// stop sequence
+    /// stop sequence, matched on the generated text
     /// Default: None
     pub stop_sequence: Option<String>,
 
+    /// stop sequences, generation stops at the first one found in the generated text
+    /// Default: None
+    pub stop_sequences: Option<Vec<String>>,
+
     /// consider newlines as a repeatable token
     /// Default: true
     pub penalize_nl: Option<bool>,
 
+    /// number of prompt tokens to keep when the context is full and older tokens are dropped
+    /// Default: 0
+    /// -1 = keep the whole prompt
+    pub n_keep: Option<i32>,
+
+    /// seed of the sampling rng for this request
+    /// Default: None, continue from the current rng state
+    /// < 0 = random seed
+    pub seed: Option<i32>,
+
+    /// how embedding inputs longer than the context are handled
+    /// Default: Truncate
+    pub embedding_overflow: Option<EmbeddingOverflow>,
+
+    /// L2 normalize the embedding
+    /// Default: false
+    pub normalize_embedding: Option<bool>,
+
     /// prompt
     pub prompt: String,
 }
@@ -182,3 +303,27 @@ pub struct LlamaLoraAdaptor {
     pub lora_base: Option<String>,
     pub n_threads: i32,
 }
+
+// The tensor types a model can be quantized to, only used by the Rust API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuantizeType {
+    F16,
+    Q4_0,
+    Q4_1,
+    Q5_0,
+    Q5_1,
+    Q8_0,
+}
+
+impl From<QuantizeType> for llama_ftype {
+    fn from(quantize_type: QuantizeType) -> Self {
+        match quantize_type {
+            QuantizeType::F16 => llama_ftype_LLAMA_FTYPE_MOSTLY_F16,
+            QuantizeType::Q4_0 => llama_ftype_LLAMA_FTYPE_MOSTLY_Q4_0,
+            QuantizeType::Q4_1 => llama_ftype_LLAMA_FTYPE_MOSTLY_Q4_1,
+            QuantizeType::Q5_0 => llama_ftype_LLAMA_FTYPE_MOSTLY_Q5_0,
+            QuantizeType::Q5_1 => llama_ftype_LLAMA_FTYPE_MOSTLY_Q5_1,
+            QuantizeType::Q8_0 => llama_ftype_LLAMA_FTYPE_MOSTLY_Q8_0,
+        }
+    }
+}
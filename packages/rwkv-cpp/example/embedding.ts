@@ -0,0 +1,27 @@
+import { Rwkv } from "../index";
+import path from "path";
+
+const run = async () => {
+    const rwkv = await Rwkv.load(
+        {
+            modelPath: path.resolve(
+                process.cwd(),
+                "../../ggml-rwkv-4_raven-7b-v9-Eng99%-20230412-ctx8192-Q4_1_0.bin"
+            ),
+            tokenizerPath: path.resolve(
+                process.cwd(),
+                "../../20B_tokenizer.json"
+            ),
+            nThreads: 4,
+        },
+        true
+    );
+
+    const prompt = `Who is the president of the United States?`;
+
+    rwkv.getEmbedding(prompt, true).then((data) => {
+        console.log(data);
+    });
+};
+
+run();
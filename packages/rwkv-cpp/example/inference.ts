@@ -3,12 +3,17 @@ import path from "path";
 
 const run = async () => {
     const rwkv = await Rwkv.load(
-        path.resolve(
-            process.cwd(),
-            "../../ggml-rwkv-4_raven-7b-v9-Eng99%-20230412-ctx8192-Q4_1_0.bin"
-        ),
-        path.resolve(process.cwd(), "../../20B_tokenizer.json"),
-        4,
+        {
+            modelPath: path.resolve(
+                process.cwd(),
+                "../../ggml-rwkv-4_raven-7b-v9-Eng99%-20230412-ctx8192-Q4_1_0.bin"
+            ),
+            tokenizerPath: path.resolve(
+                process.cwd(),
+                "../../20B_tokenizer.json"
+            ),
+            nThreads: 4,
+        },
         true
     );
 
@@ -8,7 +8,16 @@ export interface RwkvInvocation {
   topP: number
   temp: number
   endToken?: number
+  /**
+   * Stop sequence, matched on the generated text
+   * Default: None
+   */
   endString?: string
+  /**
+   * Stop sequences, generation stops at the first one found in the generated text
+   * Default: None
+   */
+  stopSequences?: Array<string>
   seed?: number
   prompt: string
   isSkipGeneration?: boolean
@@ -16,24 +25,145 @@ export interface RwkvInvocation {
   isOverwriteSessionFile?: boolean
   presencePenalty?: number
   frequencyPenalty?: number
+  /**
+   * Multiplier applied to the penalty occurrence of every token after each step, ChatRWKV uses 0.996
+   * Default: 1.0 (no decay)
+   */
+  penaltyDecay?: number
+  /**
+   * Apply presence and frequency penalties to whitespace only tokens
+   * Default: true
+   */
+  penalizeWhitespace?: boolean
+  /**
+   * Apply presence and frequency penalties to punctuation only tokens
+   * Default: true
+   */
+  penalizePunctuation?: boolean
+  /**
+   * Tokens that are never penalized
+   * Default: None
+   */
+  penaltyIgnoreTokens?: Array<number>
 }
 export interface InferenceToken {
   token: string
-  completed: boolean
 }
 export const enum InferenceResultType {
   Error = 'Error',
   Data = 'Data',
   End = 'End'
 }
+export const enum FinishReason {
+  /** the model generated the end token */
+  Eos = 'Eos',
+  /** the end string was generated */
+  StopSequence = 'StopSequence',
+  /** max_predict_length tokens were generated, or generation was skipped */
+  Length = 'Length',
+  /** the prompt does not fit in the context, never reported by RWKV */
+  ContextFull = 'ContextFull',
+  /** the inference was aborted by the caller */
+  Aborted = 'Aborted',
+  /** the inference failed, see the Error event */
+  Error = 'Error'
+}
+export interface InferenceUsage {
+  /** number of tokens in the prompt */
+  nPromptTokens: number
+  /** number of tokens generated */
+  nGeneratedTokens: number
+}
 export interface InferenceResult {
   type: InferenceResultType
   data?: InferenceToken
+  /** why the generation ended, set on the End event */
+  finishReason?: FinishReason
+  /** token counts of the request, set on the End event */
+  usage?: InferenceUsage
+  /** the full generated text, set on the End event */
+  text?: string
   message?: string
 }
+export interface EmbeddingProgress {
+  /** number of texts embedded so far */
+  nProcessed: number
+  /** number of texts in the batch */
+  nTotal: number
+}
+export interface ModelLoad {
+  /** Path of the model */
+  modelPath: string
+  /**
+   * Path of the tokenizer json, cannot be set together with `tokenizer_json`
+   * Default: None, the bundled 20B tokenizer is used
+   */
+  tokenizerPath?: string
+  /**
+   * Content of the tokenizer json, cannot be set together with `tokenizer_path`
+   * Default: None
+   */
+  tokenizerJson?: string
+  /**
+   * Number of threads used for evaluation, fixed for the lifetime of the model by rwkv.cpp
+   * Default: number of physical cores
+   */
+  nThreads: number
+  /**
+   * Number of layers offloaded to the GPU, requires a cuBLAS build
+   * Default: 0
+   */
+  nGpuLayers: number
+  /**
+   * Let rwkv.cpp print errors to stderr
+   * Default: None, keeps the rwkv.cpp default
+   */
+  printErrors?: boolean
+  /**
+   * Number of generations that can run on the loaded model at the same time. Every token
+   * evaluation is serialized on the model, so concurrent generations are interleaved token by
+   * token and do not add throughput
+   * Default: 1
+   */
+  maxConcurrency: number
+}
 export type RWKV = Rwkv
 export class Rwkv {
-  static load(modelPath: string, tokenizerPath: string, nThreads: number, enableLogger: boolean): Promise<Rwkv>
-  tokenize(params: string): Promise<Array<number>>
-  inference(params: RwkvInvocation, callback: (result: InferenceResult) => void): () => void
+  /**
+   * Load a RWKV model. The tokenizer is read from `tokenizer_json` or `tokenizer_path`, and
+   * falls back to the bundled 20B tokenizer.
+   * Aborting `signal` rejects with an error whose code is `Cancelled`.
+   */
+  static load(params: Partial<ModelLoad>, enableLogger: boolean, signal?: AbortSignal): Promise<Rwkv>
+  tokenize(params: string, signal?: AbortSignal): Promise<Array<number>>
+  /** Get a sentence embedding from the hidden state of the last layer after feeding the text. */
+  getEmbedding(text: string, normalize?: boolean | undefined | null, signal?: AbortSignal): Promise<Array<number>>
+  /**
+   * Get the embeddings of a list of texts in one call, the session is reused between texts.
+   * `progress` is called after each text is embedded.
+   */
+  getEmbeddings(texts: Array<string>, normalize?: boolean | undefined | null, progress?: (progress: EmbeddingProgress) => void, signal?: AbortSignal): Promise<number[][]>
+  /**
+   * Same as `inference`, but the results are pulled from the returned stream. At most
+   * `buffer_size` results are buffered, the generation waits until they are read. Aborting
+   * `signal`, or calling `abort` on the stream, stops the prompt feeding or the generation.
+   */
+  inferenceStream(params: RwkvInvocation, bufferSize?: number | undefined | null, signal?: AbortSignal): InferenceStream
+  /**
+   * Streaming the inference result to the callback. Aborting `signal`, or calling the returned
+   * function, stops the prompt feeding or the generation.
+   */
+  inference(params: RwkvInvocation, callback: (result: InferenceResult) => void, signal?: AbortSignal): () => void
+  /**
+   * Waits for the running calls to finish and frees the model right away, instead of when
+   * the object is garbage collected. Later calls reject, disposing twice is a no-op.
+   */
+  dispose(): Promise<void>
+}
+/** Inference results of `RWKV.inferenceStream`, read with `next` until it resolves null. */
+export class InferenceStream {
+  /** Resolves the next result, or null once the End event has been read. */
+  next(): Promise<InferenceResult | null>
+  /** Stops the generation, the results produced so far can still be read. */
+  abort(): void
 }
@@ -252,7 +252,9 @@ if (!nativeBinding) {
   throw new Error(`Failed to load native binding`)
 }
 
-const { InferenceResultType, Rwkv } = nativeBinding
+const { InferenceResultType, FinishReason, Rwkv, InferenceStream } = nativeBinding
 
 module.exports.InferenceResultType = InferenceResultType
+module.exports.FinishReason = FinishReason
 module.exports.Rwkv = Rwkv
+module.exports.InferenceStream = InferenceStream
@@ -1,37 +1,138 @@
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
 use std::fs::File;
 use std::io::{Read, Write};
+use std::sync::Mutex;
 use tokenizers::tokenizer::Tokenizer;
 
 use anyhow::Result;
+use common_rs::{abort::AbortFlag, error::ErrorCode};
 use serde::{Deserialize, Serialize};
 use rwkv_sys::{
     rwkv_context, rwkv_eval, rwkv_free, rwkv_get_logits_buffer_element_count,
     rwkv_get_state_buffer_element_count, rwkv_get_system_info_string, rwkv_init_from_file,
-    rwkv_gpu_offload_layers
+    rwkv_gpu_offload_layers, rwkv_quantize_model_file, rwkv_set_print_errors, RWKV_FILE_MAGIC
 };
 
+use crate::types::ModelLoad;
+
+// The 20B tokenizer used by the RWKV-4 Pile and Raven models, bundled so a path is optional.
+const EMBEDDED_TOKENIZER: &[u8] = include_bytes!("../../../20B_tokenizer.json");
+
+// Where the tokenizer of a RWKVContext is loaded from.
+pub enum TokenizerSource {
+    File(String),
+    Json(Vec<u8>),
+    Embedded,
+}
+
+impl TokenizerSource {
+    // Picks the tokenizer from the load params, setting both the json and the path is an error.
+    pub fn from_params(params: &ModelLoad) -> Result<Self, napi::Error> {
+        match (&params.tokenizer_json, &params.tokenizer_path) {
+            (Some(_), Some(_)) => Err(ErrorCode::InvalidParam
+                .error("Only one of tokenizerJson and tokenizerPath can be set")),
+            (Some(json), None) => Ok(TokenizerSource::Json(json.as_bytes().to_vec())),
+            (None, Some(path)) => Ok(TokenizerSource::File(path.clone())),
+            (None, None) => Ok(TokenizerSource::Embedded),
+        }
+    }
+
+    pub fn load(&self) -> Result<Tokenizer, napi::Error> {
+        let tokenizer = match self {
+            TokenizerSource::File(path) => Tokenizer::from_file(path),
+            TokenizerSource::Json(json) => Tokenizer::from_bytes(json),
+            TokenizerSource::Embedded => Tokenizer::from_bytes(EMBEDDED_TOKENIZER),
+        };
+
+        tokenizer.map_err(|e| {
+            ErrorCode::ModelLoadFailed.error(format!("Failed to load tokenizer: {}", e))
+        })
+    }
+}
+
 #[napi(object)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct RWKVInvocation {
     pub max_predict_length: i32,
     pub top_p: f64,
     pub temp: f64,
     pub end_token: Option<i32>,
+    /// Stop sequence, matched on the generated text
+    /// Default: None
     pub end_string: Option<String>,
+    /// Stop sequences, generation stops at the first one found in the generated text
+    /// Default: None
+    pub stop_sequences: Option<Vec<String>>,
     pub seed: Option<i32>,
     pub prompt: String,
     pub is_skip_generation: Option<bool>,
     pub session_file_path: Option<String>,
     pub is_overwrite_session_file: Option<bool>,
     pub presence_penalty: Option<f64>,
-    pub frequency_penalty: Option<f64>
+    pub frequency_penalty: Option<f64>,
+    /// Multiplier applied to the penalty occurrence of every token after each step, ChatRWKV uses 0.996
+    /// Default: 1.0 (no decay)
+    pub penalty_decay: Option<f64>,
+    /// Apply presence and frequency penalties to whitespace only tokens
+    /// Default: true
+    pub penalize_whitespace: Option<bool>,
+    /// Apply presence and frequency penalties to punctuation only tokens
+    /// Default: true
+    pub penalize_punctuation: Option<bool>,
+    /// Tokens that are never penalized
+    /// Default: None
+    pub penalty_ignore_tokens: Option<Vec<i32>>,
+}
+
+// Model dimensions read from the rwkv.cpp file header.
+#[derive(Debug, Clone, Copy)]
+pub struct RWKVModelHeader {
+    pub version: u32,
+    pub n_vocab: u32,
+    pub n_embed: u32,
+    pub n_layer: u32,
+    /// The rwkv.cpp tensor type, see `data_type_name`
+    pub data_type: u32,
+}
+
+impl RWKVModelHeader {
+    // The file starts with `magic, version, n_vocab, n_embed, n_layer, data_type` as little
+    // endian u32.
+    pub fn read_from_file(path: &str) -> Option<Self> {
+        let mut file = File::open(path).ok()?;
+        let mut buffer = [0u8; 24];
+        file.read_exact(&mut buffer).ok()?;
+
+        let field = |i: usize| u32::from_le_bytes(buffer[i * 4..i * 4 + 4].try_into().unwrap());
+        if field(0) != RWKV_FILE_MAGIC {
+            return None;
+        }
+
+        Some(Self {
+            version: field(1),
+            n_vocab: field(2),
+            n_embed: field(3),
+            n_layer: field(4),
+            data_type: field(5),
+        })
+    }
+
+    // Names of the rwkv.cpp data types, Q4_1_O, Q4_2 and Q4_3 are no longer supported by it.
+    pub fn data_type_name(&self) -> Option<&'static str> {
+        const NAMES: [&str; 10] = [
+            "FP32", "FP16", "Q4_0", "Q4_1", "Q4_1_O", "Q4_2", "Q4_3", "Q5_0", "Q5_1", "Q8_0",
+        ];
+        NAMES.get(self.data_type as usize).copied()
+    }
 }
 
 // Represents the RWKVContext which wraps FFI calls to the rwkv.cpp library.
-#[derive(Clone)]
 pub struct RWKVContext {
     ctx: *mut rwkv_context,
+    pub header: Option<RWKVModelHeader>,
+    // rwkv.cpp keeps a single compute graph per context, so evaluations have to take turns.
+    // Everything else (state, logits, sampling, tokenizing) lives in the sessions.
+    eval_lock: Mutex<()>,
     pub tokenizer: Tokenizer,
 }
 
@@ -56,7 +157,7 @@ pub struct RWKVSessionData {
 }
 
 impl<'a> RWKVSession<'a> {
-    pub fn save_to_file(&mut self, path: &str) {
+    pub fn save_to_file(&mut self, path: &str) -> Result<(), napi::Error> {
         let data = RWKVSessionData {
             state_buffer_element_count: self.state_buffer_element_count,
             logits_buffer_element_count: self.logits_buffer_element_count,
@@ -65,46 +166,101 @@ impl<'a> RWKVSession<'a> {
             is_first: self.is_first
         };
 
-        let mut file = File::create(path).expect("Could not create file");
-        let serialized = bincode::serialize(&data).expect("Could not serialize session data");
-        file.write_all(&serialized).expect("Could not write session data");
+        let save_error = |e: &dyn std::fmt::Display| {
+            ErrorCode::InferenceFailed.error(format!("Failed to save session to {}: {}", path, e))
+        };
+        let serialized = bincode::serialize(&data).map_err(|e| save_error(&e))?;
+        File::create(path)
+            .and_then(|mut file| file.write_all(&serialized))
+            .map_err(|e| save_error(&e))?;
         log::info!("success on save to {}", path);
+        Ok(())
     }
 
-    pub fn load_from_file_or_create(path: &str, rwkv_context: &'a RWKVContext) -> RWKVSession<'a> {
+    // A missing file starts a new session, a file that cannot be read or was saved by another
+    // model is an error.
+    pub fn load_from_file_or_create(path: &str, rwkv_context: &'a RWKVContext) -> Result<RWKVSession<'a>, napi::Error> {
         match File::open(path) {
             Ok(mut file) => {
+                let invalid_session = |e: &dyn std::fmt::Display| {
+                    ErrorCode::InvalidParam.error(format!("Invalid session file {}: {}", path, e))
+                };
                 let mut buffer = Vec::new();
-                file.read_to_end(&mut buffer).expect("Could not read session data");
-                let data: RWKVSessionData = bincode::deserialize(&buffer).expect("Could not deserialize session data");
+                file.read_to_end(&mut buffer).map_err(|e| invalid_session(&e))?;
+                let data: RWKVSessionData = bincode::deserialize(&buffer).map_err(|e| invalid_session(&e))?;
+
+                let session = rwkv_context.create_new_session();
+                if data.model_state.len() != session.state_buffer_element_count
+                    || data.logits.len() != session.logits_buffer_element_count
+                {
+                    return Err(invalid_session(&"it was saved by another model"));
+                }
+
                 log::info!("success on load {}", path);
-                RWKVSession{
-                    rwkv_context,
-                    state_buffer_element_count: data.state_buffer_element_count,
-                    logits_buffer_element_count: data.logits_buffer_element_count,
-                    model_tokens: Vec::new(),
+                Ok(RWKVSession {
                     model_state: data.model_state,
                     logits: data.logits,
-                    is_first: data.is_first
-                }
+                    is_first: data.is_first,
+                    ..session
+                })
 
             }
             Err(_e) => {
-                rwkv_context.create_new_session()
+                Ok(rwkv_context.create_new_session())
             }
         }
     }
 
-    pub fn process_tokens(&mut self, tokens: &[u32]) {
-        self.model_tokens.append(&mut tokens.to_vec());
+    // Forgets every token fed so far, the buffers are kept for the next input.
+    pub fn reset(&mut self) {
+        self.model_tokens.clear();
+        self.is_first = true;
+    }
 
+    // Feeds the tokens one by one, returns false when aborted before all of them were evaluated.
+    pub fn process_tokens(&mut self, tokens: &[u32], abort: &AbortFlag) -> Result<bool, napi::Error> {
         for token in tokens.iter() {
-            self.rwkv_eval(*token).unwrap();
+            if abort.is_aborted() {
+                return Ok(false);
+            }
+            self.rwkv_eval(*token)?;
+            self.model_tokens.push(*token);
+        }
+        Ok(true)
+    }
+
+    // Returns the WKV vector (aa / bb) of the last layer, which is the attention-weighted average
+    // of every value fed into the session so far. The state is laid out per layer as
+    // [att_xx, att_aa, att_bb, att_pp, ffn_xx], each `n_embed` floats long.
+    pub fn final_layer_wkv(&self) -> Option<Vec<f32>> {
+        let header = self.rwkv_context.header?;
+        let n_embed = header.n_embed as usize;
+        let n_layer = header.n_layer as usize;
+
+        if n_embed * n_layer * 5 != self.model_state.len() || self.is_first {
+            return None;
         }
+
+        let layer = &self.model_state[(n_layer - 1) * 5 * n_embed..];
+        let aa = &layer[n_embed..2 * n_embed];
+        let bb = &layer[2 * n_embed..3 * n_embed];
+
+        Some(
+            aa.iter()
+                .zip(bb.iter())
+                .map(|(a, b)| if *b == 0.0 { 0.0 } else { a / b })
+                .collect(),
+        )
     }
 
     // Evaluates the given tokens with the specified configuration.
-    pub fn rwkv_eval(&mut self, token: u32) -> Result<(), ()> {
+    pub fn rwkv_eval(&mut self, token: u32) -> Result<(), napi::Error> {
+        if self.model_state.len() != self.state_buffer_element_count
+            || self.logits.len() != self.logits_buffer_element_count
+        {
+            return Err(ErrorCode::InferenceFailed.error("Session buffer size mismatch"));
+        }
+
         let state_in = if self.is_first {
             self.is_first = false;
             std::ptr::null_mut()
@@ -115,15 +271,7 @@ impl<'a> RWKVSession<'a> {
         let state_out = self.model_state.as_mut_ptr();
         let logits_out = self.logits.as_mut_ptr();
 
-        assert!(
-            self.model_state.len() == self.state_buffer_element_count,
-            "state buffer size mismatch"
-        );
-
-        assert!(
-            self.logits.len() == self.logits_buffer_element_count,
-            "logits buffer size mismatch"
-        );
+        let _guard = self.rwkv_context.eval_lock.lock().unwrap();
 
         let res = unsafe {
             rwkv_eval(
@@ -138,21 +286,69 @@ impl<'a> RWKVSession<'a> {
         if res {
             Ok(())
         } else {
-            Err(())
+            Err(ErrorCode::InferenceFailed.error(format!("Failed to evaluate token {}", token)))
         }
     }
 }
 
 impl RWKVContext {
     // Creates a new RWKVContext from the specified file and configuration parameters.
-    pub fn new(model_path: &str, tokenizer_path: &str, n_threads: u32, n_gpu_layers: u32) -> Self {
-        let ctx = unsafe { rwkv_init_from_file(model_path.as_ptr() as *const i8, n_threads) };
-        if n_gpu_layers > 0  {
-            unsafe { rwkv_gpu_offload_layers(ctx, n_gpu_layers) };
+    pub fn new(params: &ModelLoad, tokenizer: &TokenizerSource) -> Result<Self, napi::Error> {
+        let tokenizer = tokenizer.load()?;
+        let model_path = params.model_path.as_str();
+        let model_path_c = CString::new(model_path)
+            .map_err(|e| ErrorCode::InvalidParam.error(format!("Invalid model path: {}", e)))?;
+        let ctx = unsafe { rwkv_init_from_file(model_path_c.as_ptr(), params.n_threads) };
+        if ctx.is_null() {
+            return Err(ErrorCode::ModelLoadFailed.error(format!(
+                "Failed to initialize RWKV context from file: {}",
+                model_path
+            )));
+        }
+        if let Some(print_errors) = params.print_errors {
+            unsafe { rwkv_set_print_errors(ctx, print_errors) };
         }
-        let tokenizer = Tokenizer::from_file(tokenizer_path).unwrap();
+        if params.n_gpu_layers > 0  {
+            unsafe { rwkv_gpu_offload_layers(ctx, params.n_gpu_layers) };
+        }
+        let header = RWKVModelHeader::read_from_file(model_path);
+
+        Ok(Self {
+            ctx,
+            header,
+            eval_lock: Mutex::new(()),
+            tokenizer,
+        })
+    }
 
-        Self { ctx, tokenizer }
+    // Writes a quantized copy of the model at `input` to `output`, `format` is one of the rwkv.cpp
+    // format names, e.g. `Q5_1`.
+    pub fn rwkv_quantize_model_file(
+        input: &str,
+        output: &str,
+        format: &str,
+    ) -> Result<(), napi::Error> {
+        let c_string = |value: &str, name: &str| {
+            CString::new(value)
+                .map_err(|e| ErrorCode::InvalidParam.error(format!("Invalid {}: {}", name, e)))
+        };
+        let input_path = c_string(input, "model path")?;
+        let output_path = c_string(output, "model path")?;
+        let format_name = c_string(format, "quantize format")?;
+
+        let quantized = unsafe {
+            rwkv_quantize_model_file(
+                input_path.as_ptr(),
+                output_path.as_ptr(),
+                format_name.as_ptr(),
+            )
+        };
+
+        if !quantized {
+            return Err(ErrorCode::ModelLoadFailed
+                .error(format!("Failed to quantize model {} to {}", input, format)));
+        }
+        Ok(())
     }
 
     pub fn rwkv_print_system_info_string(&self) {
@@ -195,7 +391,7 @@ impl RWKVContext {
 
 // Provides thread-safe behavior for RWKVContext.
 unsafe impl Send for RWKVContext {}
-// TODO: this is not Sync-able
+// Sessions own their buffers and evaluations are serialized by `eval_lock`.
 unsafe impl Sync for RWKVContext {}
 
 // Enables dereferencing RWKVContext to access the underlying *mut rwkv_context.
@@ -0,0 +1,263 @@
+use std::sync::Arc;
+use common_rs::{
+    abort::AbortFlag,
+    callback::Callback,
+    error::{parse_params, ErrorCode},
+    handle::ModelHandle,
+    logger::LLamaLogger,
+    stream::{result_stream, run_inference, InferenceEvent, ResultStream},
+};
+
+use crate::context::{RWKVInvocation, TokenizerSource};
+use napi::{bindgen_prelude::*, JsFunction, JsObject};
+use crate::rwkv::RWKVInternal;
+use tokio::sync::Semaphore;
+use crate::types::{
+    EmbeddingProgress, FinishReason, InferenceResult, InferenceResultType, ModelLoad,
+};
+
+#[napi]
+pub struct RWKV {
+    rwkv: ModelHandle<RWKVInternal>,
+    // Limits how many generations can share the loaded model at the same time.
+    semaphore: Arc<Semaphore>,
+}
+
+#[napi]
+impl RWKV {
+    /// Load a RWKV model. The tokenizer is read from `tokenizer_json` or `tokenizer_path`, and
+    /// falls back to the bundled 20B tokenizer.
+    /// Aborting `signal` rejects with an error whose code is `Cancelled`.
+    #[napi(ts_return_type = "Promise<Rwkv>")]
+    pub fn load(
+        env: Env,
+        #[napi(ts_arg_type = "Partial<ModelLoad>")] params: serde_json::Value,
+        enable_logger: bool,
+        #[napi(ts_arg_type = "AbortSignal")] signal: Option<JsObject>,
+    ) -> Result<JsObject> {
+        let params = parse_params::<ModelLoad>(params)?;
+        let tokenizer = TokenizerSource::from_params(&params)?;
+
+        let logger = LLamaLogger::get_singleton();
+
+        logger.set_enabled(enable_logger);
+
+        let max_concurrency = params.max_concurrency.max(1) as usize;
+        let abort = AbortFlag::from_signal(&env, signal)?;
+
+        env.execute_tokio_future(
+            async move { RWKVInternal::load(&params, tokenizer, enable_logger, &abort).await },
+            move |_, rwkv| {
+                Ok(RWKV {
+                    rwkv: ModelHandle::new(rwkv),
+                    semaphore: Arc::new(Semaphore::new(max_concurrency)),
+                })
+            },
+        )
+    }
+
+    #[napi(ts_return_type = "Promise<Array<number>>")]
+    pub fn tokenize(
+        &self,
+        env: Env,
+        params: String,
+        #[napi(ts_arg_type = "AbortSignal")] signal: Option<JsObject>,
+    ) -> Result<JsObject> {
+        let abort = AbortFlag::from_signal(&env, signal)?;
+        let rwkv = self.rwkv.clone();
+
+        env.execute_tokio_future(
+            async move {
+                let rwkv = rwkv.read().await?;
+                abort.check()?;
+                rwkv.tokenize(&params).await
+            },
+            |_, tokens| Ok(tokens),
+        )
+    }
+
+    /// Get a sentence embedding from the hidden state of the last layer after feeding the text.
+    #[napi(ts_return_type = "Promise<Array<number>>")]
+    pub fn get_embedding(
+        &self,
+        env: Env,
+        text: String,
+        normalize: Option<bool>,
+        #[napi(ts_arg_type = "AbortSignal")] signal: Option<JsObject>,
+    ) -> Result<JsObject> {
+        let abort = AbortFlag::from_signal(&env, signal)?;
+        let rwkv = self.rwkv.clone();
+        let semaphore = self.semaphore.clone();
+
+        env.execute_tokio_future(
+            async move {
+                let _permit = semaphore.acquire_owned().await.unwrap();
+                let rwkv = rwkv.read().await?;
+
+                tokio::task::spawn_blocking(move || {
+                    rwkv.embedding(&text, normalize.unwrap_or(false), &abort)
+                })
+                .await
+                .map_err(|e| ErrorCode::InferenceFailed.error(format!("Failed to run embedding: {}", e)))?
+            },
+            |_, embedding| Ok(embedding),
+        )
+    }
+
+    /// Get the embeddings of a list of texts in one call, the session is reused between texts.
+    /// `progress` is called after each text is embedded.
+    #[napi(ts_return_type = "Promise<number[][]>")]
+    pub fn get_embeddings(
+        &self,
+        env: Env,
+        texts: Vec<String>,
+        normalize: Option<bool>,
+        #[napi(ts_arg_type = "(progress: EmbeddingProgress) => void")] progress: Option<JsFunction>,
+        #[napi(ts_arg_type = "AbortSignal")] signal: Option<JsObject>,
+    ) -> Result<JsObject> {
+        let abort = AbortFlag::from_signal(&env, signal)?;
+        let progress = progress.as_ref().map(Callback::new).transpose()?;
+
+        let rwkv = self.rwkv.clone();
+        let semaphore = self.semaphore.clone();
+
+        env.execute_tokio_future(
+            async move {
+                let _permit = semaphore.acquire_owned().await.unwrap();
+                let rwkv = rwkv.read().await?;
+                let n_total = texts.len() as u32;
+
+                tokio::task::spawn_blocking(move || {
+                    rwkv.embeddings(&texts, normalize.unwrap_or(false), &abort, |n_processed| {
+                        if let Some(progress) = &progress {
+                            progress.call(EmbeddingProgress {
+                                n_processed: n_processed as u32,
+                                n_total,
+                            });
+                        }
+                    })
+                })
+                .await
+                .map_err(|e| ErrorCode::InferenceFailed.error(format!("Failed to run embedding: {}", e)))?
+            },
+            |_, embeddings| Ok(embeddings),
+        )
+    }
+
+    /// Same as `inference`, but the results are pulled from the returned stream. At most
+    /// `buffer_size` results are buffered, the generation waits until they are read. Aborting
+    /// `signal`, or calling `abort` on the stream, stops the prompt feeding or the generation.
+    #[napi]
+    pub fn inference_stream(
+        &self,
+        env: Env,
+        params: RWKVInvocation,
+        buffer_size: Option<u32>,
+        #[napi(ts_arg_type = "AbortSignal")] signal: Option<JsObject>,
+    ) -> Result<InferenceStream> {
+        let abort = AbortFlag::from_signal(&env, signal)?;
+        let (sender, stream) = result_stream(buffer_size, abort.clone());
+
+        self.spawn_inference(params, abort, move |result| sender.blocking_send(result));
+
+        Ok(InferenceStream { stream })
+    }
+
+    /// Streaming the inference result to the callback. Aborting `signal`, or calling the returned
+    /// function, stops the prompt feeding or the generation.
+    #[napi(ts_return_type = "() => void")]
+    pub fn inference(
+        &self,
+        env: Env,
+        params: RWKVInvocation,
+        #[napi(ts_arg_type = "(result: InferenceResult) => void")] callback: JsFunction,
+        #[napi(ts_arg_type = "AbortSignal")] signal: Option<JsObject>,
+    ) -> Result<JsFunction> {
+        let callback = Callback::new(&callback)?;
+        let abort = AbortFlag::from_signal(&env, signal)?;
+
+        self.spawn_inference(params, abort.clone(), move |result| callback.call(result));
+
+        // The returned function may outlive the task, it should not keep the listener on `signal`.
+        let abort = abort.detached();
+        env.create_function_from_closure("abort_inference", move |_| {
+            abort.abort();
+            Ok(())
+        })
+    }
+
+    /// Waits for the running calls to finish and frees the model right away, instead of when
+    /// the object is garbage collected. Later calls reject, disposing twice is a no-op.
+    #[napi]
+    pub async fn dispose(&self) -> Result<()> {
+        self.rwkv.dispose().await;
+        Ok(())
+    }
+
+    // Waits for a free slot on the runtime, so queued generations do not hold blocking threads,
+    // then runs the inference on a blocking thread. The permit is released once it finishes.
+    fn spawn_inference(
+        &self,
+        params: RWKVInvocation,
+        abort: AbortFlag,
+        send: impl Fn(InferenceResult) + Send + 'static,
+    ) {
+        let rwkv = self.rwkv.clone();
+        let semaphore = self.semaphore.clone();
+
+        tokio::spawn(async move {
+            let permit = semaphore.acquire_owned().await.unwrap();
+            tokio::task::spawn_blocking(move || {
+                run_inference(send, |send| {
+                    rwkv.blocking_read()?.inference(&params, &abort, send)
+                });
+                drop(permit);
+            });
+        });
+    }
+}
+
+impl InferenceEvent for InferenceResult {
+    fn error(message: String) -> Self {
+        InferenceResult {
+            r#type: InferenceResultType::Error,
+            message: Some(message),
+            data: None,
+            finish_reason: None,
+            usage: None,
+            text: None,
+        }
+    }
+
+    fn failed() -> Self {
+        InferenceResult {
+            r#type: InferenceResultType::End,
+            message: None,
+            data: None,
+            finish_reason: Some(FinishReason::Error),
+            usage: None,
+            text: None,
+        }
+    }
+}
+
+/// Inference results of `RWKV.inferenceStream`, read with `next` until it resolves null.
+#[napi]
+pub struct InferenceStream {
+    stream: ResultStream<InferenceResult>,
+}
+
+#[napi]
+impl InferenceStream {
+    /// Resolves the next result, or null once the End event has been read.
+    #[napi]
+    pub async fn next(&self) -> Result<Option<InferenceResult>> {
+        Ok(self.stream.next().await)
+    }
+
+    /// Stops the generation, the results produced so far can still be read.
+    #[napi]
+    pub fn abort(&self) {
+        self.stream.abort();
+    }
+}
@@ -1,39 +1,51 @@
-use std::collections::HashMap;
-use std::ops::Add;
-use std::sync::Arc;
+use std::collections::HashSet;
 
-use tokio::sync::Mutex;
+use common_rs::{
+    abort::{aborted_error, AbortFlag},
+    error::ErrorCode,
+    output::{StopMatch, StopSequenceMatcher},
+};
 
 use crate::{
-    context::{RWKVContext, RWKVInvocation},
-    sampling::sample_logits,
-    types::{InferenceResult, InferenceResultType, InferenceToken},
+    context::{RWKVContext, RWKVInvocation, TokenizerSource},
+    sampling::{sample_logits, TokenPenalty},
+    types::{
+        FinishReason, InferenceResult, InferenceResultType, InferenceToken, InferenceUsage,
+        ModelLoad,
+    },
 };
 use crate::context::RWKVSession;
 
-#[derive(Clone)]
 pub struct RWKVInternal {
     context: RWKVContext,
 }
 
 impl RWKVInternal {
     pub async fn load(
-        mode_path: String,
-        tokenizer_path: String,
-        n_threads: u32,
-        n_gpu_layers: u32,
+        params: &ModelLoad,
+        tokenizer: TokenizerSource,
         enable_logger: bool,
-    ) -> Arc<Mutex<Self>> {
+        abort: &AbortFlag,
+    ) -> Result<Self, napi::Error> {
+        abort.check()?;
         let rwkv = RWKVInternal {
-            context: RWKVContext::new(&mode_path, &tokenizer_path, n_threads, n_gpu_layers),
+            context: RWKVContext::new(params, &tokenizer)?,
         };
+        // rwkv.cpp cannot stop a load half way, the model is freed if the caller gave up meanwhile.
+        abort.check()?;
 
         if enable_logger {
             rwkv.context.rwkv_print_system_info_string();
         }
 
-        Arc::new(Mutex::new(rwkv))
+        Ok(rwkv)
+    }
+
+    /// Quantizes the model file at `input` into `output`, the model does not need to be loaded.
+    pub fn quantize(input: &str, output: &str, format: &str) -> Result<(), napi::Error> {
+        RWKVContext::rwkv_quantize_model_file(input, output, format)
     }
+
     pub async fn tokenize(&self, input: &str) -> Result<Vec<i32>, napi::Error> {
         let tokenizer = &self.context.tokenizer;
         let tokens_result = tokenizer.encode(input, false).map(Some).unwrap_or(None);
@@ -41,26 +53,97 @@ impl RWKVInternal {
             let tokens = result.get_ids().to_vec();
             Ok(tokens.iter().map(|x| *x as i32).collect())
         } else {
-            Err(napi::Error::from_reason("Failed to tokenize"))
+            Err(ErrorCode::InvalidParam.error("Failed to tokenize"))
+        }
+    }
+
+    pub fn embedding(
+        &self,
+        input: &str,
+        normalize: bool,
+        abort: &AbortFlag,
+    ) -> Result<Vec<f64>, napi::Error> {
+        let mut session = self.context.create_new_session();
+        self.embed(&mut session, input, normalize, abort)
+    }
+
+    // Embeds every text with a single session, which is reset between inputs.
+    pub fn embeddings(
+        &self,
+        inputs: &[String],
+        normalize: bool,
+        abort: &AbortFlag,
+        mut on_progress: impl FnMut(usize),
+    ) -> Result<Vec<Vec<f64>>, napi::Error> {
+        let mut session = self.context.create_new_session();
+        let mut embeddings = Vec::with_capacity(inputs.len());
+
+        for input in inputs.iter() {
+            session.reset();
+            embeddings.push(self.embed(&mut session, input, normalize, abort)?);
+            on_progress(embeddings.len());
+        }
+
+        Ok(embeddings)
+    }
+
+    fn embed(
+        &self,
+        session: &mut RWKVSession,
+        input: &str,
+        normalize: bool,
+        abort: &AbortFlag,
+    ) -> Result<Vec<f64>, napi::Error> {
+        let tokens = self
+            .context
+            .tokenizer
+            .encode(input, false)
+            .map_err(|e| ErrorCode::InvalidParam.error(format!("Failed to tokenize: {}", e)))?;
+
+        if tokens.get_ids().is_empty() {
+            return Err(ErrorCode::InvalidParam.error("Cannot embed an empty input"));
+        }
+
+        if !session.process_tokens(tokens.get_ids(), abort)? {
+            return Err(aborted_error());
+        }
+
+        let mut embedding = session.final_layer_wkv().ok_or_else(|| {
+            ErrorCode::InferenceFailed.error("Failed to read embedding from model state")
+        })?;
+
+        if normalize {
+            let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+            if norm > 0.0 {
+                embedding.iter_mut().for_each(|x| *x /= norm);
+            }
         }
+
+        Ok(embedding.iter().map(|&x| x as f64).collect())
     }
 
     pub fn inference(
-        &mut self,
+        &self,
         input: &RWKVInvocation,
-        running: Arc<Mutex<bool>>,
+        abort: &AbortFlag,
         callback: impl Fn(InferenceResult),
-    ) {
+    ) -> Result<(), napi::Error> {
         let end_token = input.end_token.unwrap_or(0) as usize;
-        let end_string = &input.end_string;
+        let mut stop_sequences = input.stop_sequences.clone().unwrap_or_default();
+        if let Some(end_string) = &input.end_string {
+            stop_sequences.push(end_string.clone());
+        }
+        let mut stop_matcher = StopSequenceMatcher::new(stop_sequences);
         let temp = input.temp as f32;
         let top_p = input.top_p as f32;
         let seed = input.seed.map(|x| x as u64);
 
-        let context = &mut self.context;
+        let context = &self.context;
         let tokenizer = &context.tokenizer;
         let prompt = &input.prompt;
-        let binding = tokenizer.encode(prompt.as_str(), false).unwrap();
+        let binding = tokenizer
+            .encode(prompt.as_str(), false)
+            .map_err(|e| ErrorCode::InvalidParam.error(format!("Failed to tokenize: {}", e)))?;
         let tokens = binding
             .get_ids()
             .to_vec();
@@ -71,91 +154,106 @@ impl RWKVInternal {
         let is_skip_generation = &input.is_skip_generation.unwrap_or(false);
         let presence_penalty = input.presence_penalty.unwrap_or(0.2f64) as f32;
         let frequency_penalty = input.frequency_penalty.unwrap_or(0.2f64) as f32;
+        let penalty_ignore_tokens: HashSet<u32> = input
+            .penalty_ignore_tokens
+            .as_ref()
+            .map(|tokens| tokens.iter().map(|t| *t as u32).collect())
+            .unwrap_or_default();
+        let mut penalty = TokenPenalty::new(
+            presence_penalty,
+            frequency_penalty,
+            input.penalty_decay.unwrap_or(1.0) as f32,
+            input.penalize_whitespace.unwrap_or(true),
+            input.penalize_punctuation.unwrap_or(true),
+            penalty_ignore_tokens,
+        );
 
         let mut session = match session_file_path {
             Some(path) =>
-                RWKVSession::load_from_file_or_create(path.as_str(), context),
+                RWKVSession::load_from_file_or_create(path.as_str(), context)?,
             None =>
                 context.create_new_session()
         };
 
-        session.process_tokens(&tokens);
+        let is_prompt_processed = session.process_tokens(&tokens, abort)?;
 
         let mut accumulated_token: Vec<u32> = Vec::new();
-        let mut generated_string: String = String::new();
-        let mut token_counts: HashMap<u32, u32> = HashMap::new();
+        // Everything sent to the caller, reported with the End event.
+        let mut text = String::new();
+        let mut send_token = |token: String| {
+            if !token.is_empty() {
+                text.push_str(&token);
+                callback(InferenceResult {
+                    r#type: InferenceResultType::Data,
+                    message: None,
+                    data: Some(InferenceToken { token }),
+                    finish_reason: None,
+                    usage: None,
+                    text: None,
+                });
+            }
+        };
+        let mut n_generated: u32 = 0;
+        let mut finish_reason = FinishReason::Length;
 
         for _i in 0..input.max_predict_length {
             // Check if we are aborted by caller.
-            let running = *running.blocking_lock();
-            if !running {
+            if abort.is_aborted() {
+                finish_reason = FinishReason::Aborted;
                 break;
             }
 
             let logits: &mut [f32] = session.logits.as_mut();
 
-            for (k, v) in &token_counts {
-                let penalty: f32 = presence_penalty + (*v as f32) * frequency_penalty;
-                logits[*k as usize] -= penalty
-            }
+            penalty.apply(logits);
 
             let token = sample_logits(logits, temp, top_p, &seed);
             let token_u32 = token as u32;
 
-            match token_counts.get(&token_u32) {
-                Some(v) => {
-                    token_counts.insert(token_u32, v + 1);
-                }
-                None => {
-                    token_counts.insert(token_u32, 1);
-                }
-            }
+            let token_text = context.rwkv_tokens_to_str(&[token_u32]).unwrap_or_default();
+            penalty.record(token_u32, &token_text);
 
             accumulated_token.push(token_u32);
+            n_generated += 1;
 
-            let decoded = context.rwkv_tokens_to_str(&accumulated_token).unwrap();
-            generated_string = generated_string.add(decoded.as_str());
+            let decoded = context.rwkv_tokens_to_str(&accumulated_token).ok_or_else(|| {
+                ErrorCode::InferenceFailed.error("Failed to decode the generated tokens")
+            })?;
 
-            let is_match_end_string: bool = match end_string {
-                Some(str) => {
-                    generated_string.contains(str)
-                }
-                None => {
-                    false
-                }
-            };
+            if token >= 50276 || token == end_token {
+                finish_reason = FinishReason::Eos;
+                break;
+            }
 
-            if token >= 50276 || token == end_token || is_match_end_string || *is_skip_generation {
-                callback(InferenceResult {
-                    r#type: InferenceResultType::Data,
-                    message: None,
-                    data: Some(InferenceToken {
-                        token: "\n\n<end>\n".to_string(),
-                        completed: true,
-                    }),
-                });
+            if *is_skip_generation {
                 break;
             }
 
+            // We can output the text once it is complete, unless it completes or may start a
+            // stop sequence.
             if !decoded.contains('\u{FFFD}') {
                 accumulated_token.clear();
-                callback(InferenceResult {
-                    r#type: InferenceResultType::Data,
-                    message: None,
-                    data: Some(InferenceToken {
-                        token: decoded,
-                        completed: false,
-                    }),
-                });
+                match stop_matcher.push(&decoded) {
+                    StopMatch::Continue(output) => send_token(output),
+                    StopMatch::Stop(output) => {
+                        send_token(output);
+                        finish_reason = FinishReason::StopSequence;
+                        break;
+                    }
+                }
             }
 
-            session.process_tokens(&[token.try_into().unwrap()]);
+            session.process_tokens(&[token_u32], abort)?;
         }
 
+        // Release the text held back for a stop sequence that never completed.
+        send_token(stop_matcher.flush());
+
+        // A session whose prompt was only partly fed is not worth saving.
         match session_file_path {
             Some(path) =>
-                if *is_overwrite_session_file {
-                    session.save_to_file(path.as_str());
+                if *is_overwrite_session_file && is_prompt_processed {
+                    session.save_to_file(path.as_str())?;
                 }
             None => {}
         };
@@ -164,6 +262,14 @@ impl RWKVInternal {
             r#type: InferenceResultType::End,
             message: None,
             data: None,
+            finish_reason: Some(finish_reason),
+            usage: Some(InferenceUsage {
+                n_prompt_tokens: tokens.len() as u32,
+                n_generated_tokens: n_generated,
+            }),
+            text: Some(text),
         });
+
+        Ok(())
     }
 }
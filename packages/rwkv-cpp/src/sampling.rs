@@ -1,4 +1,5 @@
 // a lot of codes borrowed from https://github.com/KerfuffleV2/smolrsrwkv/blob/main/smolrwkv/src/util.rs
+use std::collections::{HashMap, HashSet};
 use std::ops::{Add, Sub};
 
 use ndarray::{Array1, ArrayView1, NdFloat, ScalarOperand};
@@ -48,16 +49,15 @@ pub fn sample_probs<T: ReqOps + num_traits::AsPrimitive<f32>>(
         top_p = 1.0;
     }
 
-    // if temp == 0.0 {
-    //     // get argmax of probs
-    //     let index_of_max = probs
-    //         .iter()
-    //         .enumerate()
-    //         .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
-    //         .map(|(i, _)| i)
-    //         .unwrap_or(0);
-    //     return index_of_max;
-    // }
+    // `powf(1.0 / temp)` zeroes every weight at 0, which would always sample token 0 (EOS).
+    if temp <= 0.0 {
+        return probs
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Less))
+            .map(|(i, _)| i)
+            .unwrap_or(0);
+    }
 
     // if top_p < 1.0 {
     // sort the probs
@@ -103,3 +103,99 @@ pub fn sample_probs<T: ReqOps + num_traits::AsPrimitive<f32>>(
     };
     dist.sample(&mut rng)
 }
+
+// Presence and frequency penalties with ChatRWKV style decay: every step the occurrence of
+// each seen token is multiplied by `decay` before the sampled token is counted.
+pub struct TokenPenalty {
+    presence: f32,
+    frequency: f32,
+    decay: f32,
+    penalize_whitespace: bool,
+    penalize_punctuation: bool,
+    ignored_tokens: HashSet<u32>,
+    occurrences: HashMap<u32, f32>,
+}
+
+impl TokenPenalty {
+    pub fn new(
+        presence: f32,
+        frequency: f32,
+        decay: f32,
+        penalize_whitespace: bool,
+        penalize_punctuation: bool,
+        ignored_tokens: HashSet<u32>,
+    ) -> Self {
+        Self {
+            presence,
+            frequency,
+            decay,
+            penalize_whitespace,
+            penalize_punctuation,
+            ignored_tokens,
+            occurrences: HashMap::new(),
+        }
+    }
+
+    pub fn apply(&self, logits: &mut [f32]) {
+        for (token, occurrence) in &self.occurrences {
+            logits[*token as usize] -= self.presence + occurrence * self.frequency;
+        }
+    }
+
+    // Decays the previous occurrences and counts `token`, unless it is exempt from penalties.
+    // `text` is the decoded text of the token alone.
+    pub fn record(&mut self, token: u32, text: &str) {
+        if self.decay != 1.0 {
+            for occurrence in self.occurrences.values_mut() {
+                *occurrence *= self.decay;
+            }
+        }
+
+        if self.is_exempt(token, text) {
+            return;
+        }
+
+        *self.occurrences.entry(token).or_insert(0.0) += 1.0;
+    }
+
+    fn is_exempt(&self, token: u32, text: &str) -> bool {
+        if self.ignored_tokens.contains(&token) {
+            return true;
+        }
+
+        if text.is_empty() {
+            return false;
+        }
+
+        let is_whitespace = text.chars().all(char::is_whitespace);
+        let is_punct =
+            !is_whitespace && text.chars().all(|c| c.is_whitespace() || is_punctuation(c));
+
+        (is_whitespace && !self.penalize_whitespace) || (is_punct && !self.penalize_punctuation)
+    }
+}
+
+fn is_punctuation(c: char) -> bool {
+    c.is_ascii_punctuation() || "，。、：；？！“”‘’（）《》…—".contains(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_temperature_is_greedy() {
+        let mut logits = [0.5, 3.0, 1.0, 2.0];
+        for _ in 0..8 {
+            assert_eq!(sample_logits(&mut logits, 0.0, 0.0, &None), 1);
+            assert_eq!(sample_logits(&mut logits, -1.0, 0.9, &None), 1);
+        }
+    }
+
+    #[test]
+    fn seeded_sampling_is_repeatable() {
+        let mut logits = [0.5, 3.0, 1.0, 2.0];
+        let first = sample_logits(&mut logits, 1.0, 1.0, &Some(42));
+        assert_eq!(sample_logits(&mut logits, 1.0, 1.0, &Some(42)), first);
+    }
+}
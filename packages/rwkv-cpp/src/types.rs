@@ -1,10 +1,10 @@
 use napi::bindgen_prelude::*;
+use serde::{Deserialize, Serialize};
 
 #[napi(object)]
 #[derive(Clone, Debug)]
 pub struct InferenceToken {
     pub token: String,
-    pub completed: bool,
 }
 
 #[napi(string_enum)]
@@ -14,9 +14,97 @@ pub enum InferenceResultType {
     End,
 }
 
+#[napi(string_enum)]
+#[derive(Debug)]
+pub enum FinishReason {
+    /// the model generated the end token
+    Eos,
+    /// the end string was generated
+    StopSequence,
+    /// max_predict_length tokens were generated, or generation was skipped
+    Length,
+    /// the prompt does not fit in the context, never reported by RWKV
+    ContextFull,
+    /// the inference was aborted by the caller
+    Aborted,
+    /// the inference failed, see the Error event
+    Error,
+}
+
+#[napi(object)]
+pub struct InferenceUsage {
+    /// number of tokens in the prompt
+    pub n_prompt_tokens: u32,
+    /// number of tokens generated
+    pub n_generated_tokens: u32,
+}
+
 #[napi(object)]
 pub struct InferenceResult {
     pub r#type: InferenceResultType,
     pub data: Option<InferenceToken>,
+    /// why the generation ended, set on the End event
+    pub finish_reason: Option<FinishReason>,
+    /// token counts of the request, set on the End event
+    pub usage: Option<InferenceUsage>,
+    /// the full generated text, set on the End event
+    pub text: Option<String>,
     pub message: Option<String>,
 }
+
+#[napi(object)]
+pub struct EmbeddingProgress {
+    /// number of texts embedded so far
+    pub n_processed: u32,
+    /// number of texts in the batch
+    pub n_total: u32,
+}
+
+// Represents the configuration parameters for a RWKVContext.
+#[napi(object)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ModelLoad {
+    /// Path of the model
+    pub model_path: String,
+
+    /// Path of the tokenizer json, cannot be set together with `tokenizer_json`
+    /// Default: None, the bundled 20B tokenizer is used
+    pub tokenizer_path: Option<String>,
+
+    /// Content of the tokenizer json, cannot be set together with `tokenizer_path`
+    /// Default: None
+    pub tokenizer_json: Option<String>,
+
+    /// Number of threads used for evaluation, fixed for the lifetime of the model by rwkv.cpp
+    /// Default: number of physical cores
+    pub n_threads: u32,
+
+    /// Number of layers offloaded to the GPU, requires a cuBLAS build
+    /// Default: 0
+    pub n_gpu_layers: u32,
+
+    /// Let rwkv.cpp print errors to stderr
+    /// Default: None, keeps the rwkv.cpp default
+    pub print_errors: Option<bool>,
+
+    /// Number of generations that can run on the loaded model at the same time. Every token
+    /// evaluation is serialized on the model, so concurrent generations are interleaved token by
+    /// token and do not add throughput
+    /// Default: 1
+    pub max_concurrency: u32,
+}
+
+impl Default for ModelLoad {
+    fn default() -> Self {
+        Self {
+            model_path: "".to_string(),
+            tokenizer_path: None,
+            tokenizer_json: None,
+            n_threads: num_cpus::get_physical() as u32,
+            n_gpu_layers: 0,
+            print_errors: None,
+            max_concurrency: 1,
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::{collections::HashSet, fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::openai::ChatMessage;
+
+// Server configuration, read from a TOML file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Address to listen on
+    /// Default: 127.0.0.1
+    #[serde(default = "default_host")]
+    pub host: String,
+
+    /// Port to listen on
+    /// Default: 8080
+    #[serde(default = "default_port")]
+    pub port: u16,
+
+    /// Print the logs of the backends
+    /// Default: false
+    #[serde(default)]
+    pub enable_logger: bool,
+
+    /// Models served, all of them are loaded at startup
+    pub models: Vec<ModelConfig>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    LlamaCpp,
+    RwkvCpp,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelConfig {
+    /// Name of the model in the `model` field of the requests
+    pub id: String,
+
+    pub backend: Backend,
+
+    /// Load params of the backend, same keys as `ModelLoad` in the JS API, e.g. `modelPath`
+    pub load: toml::Value,
+
+    /// Number of threads used for generation and embeddings, llama-cpp only
+    /// Default: number of physical cores
+    #[serde(default = "default_n_threads")]
+    pub n_threads: i32,
+
+    /// Number of tokens generated when the request has no `max_tokens`
+    /// Default: 256
+    #[serde(default = "default_max_tokens")]
+    pub max_tokens: u32,
+
+    /// How chat messages are turned into a prompt
+    #[serde(default)]
+    pub chat: ChatTemplate,
+}
+
+// Chat messages are rendered as `prefix + content + separator`, the prompt ends with the
+// assistant prefix. The user prefix is a stop sequence, so the model does not answer itself.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChatTemplate {
+    /// Default: ""
+    pub system: String,
+    /// Default: "USER: "
+    pub user: String,
+    /// Default: "ASSISTANT: "
+    pub assistant: String,
+    /// Default: "\n"
+    pub separator: String,
+}
+
+impl Default for ChatTemplate {
+    fn default() -> Self {
+        Self {
+            system: "".to_string(),
+            user: "USER: ".to_string(),
+            assistant: "ASSISTANT: ".to_string(),
+            separator: "\n".to_string(),
+        }
+    }
+}
+
+impl ChatTemplate {
+    pub fn render(&self, messages: &[ChatMessage]) -> Result<String, String> {
+        let mut prompt = String::new();
+
+        for message in messages {
+            let prefix = match message.role.as_str() {
+                "system" => &self.system,
+                "user" => &self.user,
+                "assistant" => &self.assistant,
+                role => return Err(format!("Unsupported message role: {}", role)),
+            };
+            prompt.push_str(prefix);
+            prompt.push_str(&message.content);
+            prompt.push_str(&self.separator);
+        }
+        prompt.push_str(self.assistant.trim_end());
+
+        Ok(prompt)
+    }
+
+    pub fn stop_sequence(&self) -> String {
+        format!("{}{}", self.separator, self.user.trim_end())
+    }
+}
+
+fn default_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_port() -> u16 {
+    8080
+}
+
+fn default_n_threads() -> i32 {
+    num_cpus::get_physical() as i32
+}
+
+fn default_max_tokens() -> u32 {
+    256
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config {}", path.display()))?;
+        let config: Config = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse config {}", path.display()))?;
+
+        if config.models.is_empty() {
+            bail!("No model configured in {}", path.display());
+        }
+        let mut ids = HashSet::new();
+        for model in config.models.iter() {
+            if !ids.insert(model.id.as_str()) {
+                bail!("Model id {} is configured twice", model.id);
+            }
+        }
+
+        Ok(config)
+    }
+}
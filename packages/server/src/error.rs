@@ -0,0 +1,94 @@
+use axum::{
+    extract::rejection::JsonRejection,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use common_rs::error::ErrorCode;
+use serde_json::json;
+
+// An error in the OpenAI shape, `{"error": {"message", "type", "param", "code"}}`.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+    pub code: Option<&'static str>,
+}
+
+impl ApiError {
+    pub fn invalid_request(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            message: message.into(),
+            code: None,
+        }
+    }
+
+    pub fn model_not_found(model: &str) -> Self {
+        Self {
+            status: StatusCode::NOT_FOUND,
+            message: format!("The model `{}` does not exist", model),
+            code: Some("model_not_found"),
+        }
+    }
+
+    // Errors of the backends carry their code as a `[Code] ` prefix of the message.
+    pub fn from_message(message: &str) -> Self {
+        match ErrorCode::split_message(message) {
+            Some((code, text)) => Self {
+                status: match code {
+                    ErrorCode::InvalidParam | ErrorCode::ContextFull => StatusCode::BAD_REQUEST,
+                    _ => StatusCode::INTERNAL_SERVER_ERROR,
+                },
+                message: text.to_string(),
+                code: Some(match code {
+                    // The code OpenAI clients check for a prompt that is too long.
+                    ErrorCode::ContextFull => "context_length_exceeded",
+                    _ => code.as_str(),
+                }),
+            },
+            None => Self {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                message: message.to_string(),
+                code: None,
+            },
+        }
+    }
+
+    fn error_type(&self) -> &'static str {
+        if self.status.is_client_error() {
+            "invalid_request_error"
+        } else {
+            "server_error"
+        }
+    }
+
+    pub fn body(&self) -> serde_json::Value {
+        json!({
+            "error": {
+                "message": self.message,
+                "type": self.error_type(),
+                "param": null,
+                "code": self.code,
+            }
+        })
+    }
+}
+
+impl From<napi::Error> for ApiError {
+    fn from(error: napi::Error) -> Self {
+        Self::from_message(&error.reason)
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::invalid_request(rejection.body_text())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.body())).into_response()
+    }
+}
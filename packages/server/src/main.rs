@@ -0,0 +1,59 @@
+#![deny(clippy::all)]
+
+mod config;
+mod error;
+mod model;
+mod openai;
+mod routes;
+
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use common_rs::logger::LLamaLogger;
+
+use config::Config;
+use model::Model;
+use routes::AppState;
+
+/// OpenAI compatible HTTP server for llama.cpp and rwkv.cpp models
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// Path of the TOML config file
+    #[arg(short, long, default_value = "server.toml")]
+    config: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let config = Config::load(&args.config)?;
+
+    let logger = LLamaLogger::get_singleton();
+    logger.set_enabled(config.enable_logger);
+
+    let mut models = Vec::with_capacity(config.models.len());
+    for model_config in config.models {
+        println!("Loading {}", model_config.id);
+        models.push(Model::load(model_config, config.enable_logger).await?);
+    }
+
+    let addr: SocketAddr = format!("{}:{}", config.host, config.port)
+        .parse()
+        .context("Invalid host or port")?;
+    let state = Arc::new(AppState {
+        models,
+        started_at: routes::unix_time(),
+    });
+
+    println!("Listening on http://{}", addr);
+    axum::Server::bind(&addr)
+        .serve(routes::router(state).into_make_service())
+        .with_graceful_shutdown(async {
+            tokio::signal::ctrl_c().await.ok();
+        })
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,344 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use common_rs::{abort::AbortFlag, error::ErrorCode, handle::ModelHandle};
+use llama_node_cpp::{llama::LLamaInternal, types as llama_types};
+use rwkv_node_cpp::{rwkv::RWKVInternal, types as rwkv_types, RWKVInvocation, TokenizerSource};
+use tokio::sync::{mpsc, Semaphore};
+
+use crate::{
+    config::{Backend, ChatTemplate, ModelConfig},
+    error::ApiError,
+};
+
+// A generation request, already translated from the OpenAI fields.
+pub struct Completion {
+    pub prompt: String,
+    pub max_tokens: Option<u32>,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub presence_penalty: Option<f64>,
+    pub frequency_penalty: Option<f64>,
+    pub seed: Option<i32>,
+    pub stop: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinishReason {
+    Stop,
+    Length,
+    Aborted,
+    Error,
+}
+
+impl FinishReason {
+    // The OpenAI `finish_reason`, none when the generation did not end normally.
+    pub fn as_openai(&self) -> Option<&'static str> {
+        match self {
+            FinishReason::Stop => Some("stop"),
+            FinishReason::Length => Some("length"),
+            FinishReason::Aborted | FinishReason::Error => None,
+        }
+    }
+}
+
+// Backend independent inference events, an `Error` is always followed by `End`.
+pub enum CompletionEvent {
+    Token(String),
+    Error(String),
+    End {
+        finish_reason: FinishReason,
+        prompt_tokens: u32,
+        completion_tokens: u32,
+    },
+}
+
+enum LoadedModel {
+    LLama(ModelHandle<LLamaInternal>),
+    Rwkv {
+        rwkv: ModelHandle<RWKVInternal>,
+        // Limits how many generations can share the loaded model at the same time.
+        semaphore: Arc<Semaphore>,
+    },
+}
+
+pub struct Model {
+    pub id: String,
+    pub chat: ChatTemplate,
+    n_threads: i32,
+    max_tokens: u32,
+    model: LoadedModel,
+}
+
+// Aborts the work of a request whose future was dropped, e.g. when the client disconnected.
+struct AbortOnDrop(AbortFlag);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+// Buffered inference events, the generation waits when the client reads slower.
+const EVENT_BUFFER_SIZE: usize = 16;
+
+impl Model {
+    pub async fn load(config: ModelConfig, enable_logger: bool) -> Result<Self> {
+        let abort = AbortFlag::default();
+        let load_error = |e: napi::Error| anyhow!("Failed to load {}: {}", config.id, e.reason);
+
+        let model = match config.backend {
+            Backend::LlamaCpp => {
+                let params: llama_types::ModelLoad = config
+                    .load
+                    .clone()
+                    .try_into()
+                    .with_context(|| format!("Invalid load params of {}", config.id))?;
+                let llama = LLamaInternal::load(params, enable_logger, &abort)
+                    .await
+                    .map_err(load_error)?;
+                LoadedModel::LLama(ModelHandle::new(llama))
+            }
+            Backend::RwkvCpp => {
+                let params: rwkv_types::ModelLoad = config
+                    .load
+                    .clone()
+                    .try_into()
+                    .with_context(|| format!("Invalid load params of {}", config.id))?;
+                let tokenizer = TokenizerSource::from_params(&params).map_err(load_error)?;
+                let rwkv = RWKVInternal::load(&params, tokenizer, enable_logger, &abort)
+                    .await
+                    .map_err(load_error)?;
+                LoadedModel::Rwkv {
+                    rwkv: ModelHandle::new(rwkv),
+                    semaphore: Arc::new(Semaphore::new(params.max_concurrency.max(1) as usize)),
+                }
+            }
+        };
+
+        Ok(Self {
+            id: config.id,
+            chat: config.chat,
+            n_threads: config.n_threads,
+            max_tokens: config.max_tokens,
+            model,
+        })
+    }
+
+    // Starts the generation on a blocking thread, it is aborted once the receiver is dropped.
+    pub fn complete(&self, completion: Completion) -> mpsc::Receiver<CompletionEvent> {
+        let (sender, receiver) = mpsc::channel(EVENT_BUFFER_SIZE);
+        let abort = AbortFlag::default();
+        let max_tokens = completion.max_tokens.unwrap_or(self.max_tokens);
+
+        let send = {
+            let abort = abort.clone();
+            move |event: CompletionEvent| {
+                if sender.blocking_send(event).is_err() {
+                    // The receiver was dropped, stop generating.
+                    abort.abort();
+                }
+            }
+        };
+
+        match &self.model {
+            LoadedModel::LLama(llama) => {
+                let llama = llama.clone();
+                let params = llama_types::Generate {
+                    n_threads: self.n_threads,
+                    n_tok_predict: max_tokens as i32,
+                    temp: completion.temperature,
+                    top_p: completion.top_p,
+                    presence_penalty: completion.presence_penalty,
+                    frequency_penalty: completion.frequency_penalty,
+                    seed: completion.seed,
+                    stop_sequences: Some(completion.stop),
+                    prompt: completion.prompt,
+                    ..Default::default()
+                };
+
+                tokio::task::spawn_blocking(move || {
+                    let result = llama.blocking_write().and_then(|llama| {
+                        llama.inference(&params, &abort, |result| send_llama_result(&send, result))
+                    });
+                    if let Err(e) = result {
+                        send_error(&send, e);
+                    }
+                });
+            }
+            LoadedModel::Rwkv { rwkv, semaphore } => {
+                let rwkv = rwkv.clone();
+                let semaphore = semaphore.clone();
+                let params = RWKVInvocation {
+                    max_predict_length: max_tokens as i32,
+                    temp: completion.temperature.unwrap_or(1.0),
+                    top_p: completion.top_p.unwrap_or(1.0),
+                    presence_penalty: completion.presence_penalty,
+                    frequency_penalty: completion.frequency_penalty,
+                    seed: completion.seed,
+                    stop_sequences: Some(completion.stop),
+                    prompt: completion.prompt,
+                    ..Default::default()
+                };
+
+                tokio::spawn(async move {
+                    // Wait for a free slot without holding a blocking thread, the permit is
+                    // released once the generation finishes.
+                    let permit = semaphore.acquire_owned().await.unwrap();
+                    tokio::task::spawn_blocking(move || {
+                        let result = rwkv.blocking_read().and_then(|rwkv| {
+                            rwkv.inference(&params, &abort, |result| {
+                                send_rwkv_result(&send, result)
+                            })
+                        });
+                        if let Err(e) = result {
+                            send_error(&send, e);
+                        }
+                        drop(permit);
+                    });
+                });
+            }
+        }
+
+        receiver
+    }
+
+    // L2 normalized embeddings of `texts`, with the number of tokens they were made of.
+    pub async fn embed(&self, texts: Vec<String>) -> napi::Result<(Vec<Vec<f64>>, u32)> {
+        let abort = AbortFlag::default();
+        let _guard = AbortOnDrop(abort.clone());
+
+        match &self.model {
+            LoadedModel::LLama(llama) => {
+                let llama = llama.clone();
+                let params = llama_types::Generate {
+                    n_threads: self.n_threads,
+                    normalize_embedding: Some(true),
+                    ..Default::default()
+                };
+
+                tokio::task::spawn_blocking(move || {
+                    let llama = llama.blocking_write()?;
+                    let embeddings = llama.embeddings(&texts, &params, &abort, |_| {})?;
+                    let n_tokens = count_tokens(&texts, |text| {
+                        futures::executor::block_on(llama.tokenize(text))
+                    })?;
+                    Ok((embeddings, n_tokens))
+                })
+                .await
+                .map_err(|e| napi::Error::from_reason(format!("Failed to run embedding: {}", e)))?
+            }
+            LoadedModel::Rwkv { rwkv, semaphore } => {
+                let _permit = semaphore.clone().acquire_owned().await.unwrap();
+                let rwkv = rwkv.read().await?;
+
+                tokio::task::spawn_blocking(move || {
+                    let embeddings = rwkv.embeddings(&texts, true, &abort, |_| {})?;
+                    let n_tokens = count_tokens(&texts, |text| {
+                        futures::executor::block_on(rwkv.tokenize(text))
+                    })?;
+                    Ok((embeddings, n_tokens))
+                })
+                .await
+                .map_err(|e| napi::Error::from_reason(format!("Failed to run embedding: {}", e)))?
+            }
+        }
+    }
+}
+
+fn count_tokens(
+    texts: &[String],
+    tokenize: impl Fn(&str) -> napi::Result<Vec<i32>>,
+) -> napi::Result<u32> {
+    texts
+        .iter()
+        .map(|text| tokenize(text).map(|tokens| tokens.len() as u32))
+        .sum()
+}
+
+fn send_error(send: &impl Fn(CompletionEvent), error: napi::Error) {
+    send(CompletionEvent::Error(error.reason));
+    send(CompletionEvent::End {
+        finish_reason: FinishReason::Error,
+        prompt_tokens: 0,
+        completion_tokens: 0,
+    });
+}
+
+// A prompt that does not fit fails the request, instead of completing with no text.
+fn context_full_error(message: Option<String>) -> napi::Error {
+    ErrorCode::ContextFull
+        .error(message.unwrap_or_else(|| "The prompt does not fit in the context".to_string()))
+}
+
+fn send_llama_result(send: &impl Fn(CompletionEvent), result: llama_types::InferenceResult) {
+    use llama_types::{FinishReason as LLamaFinishReason, InferenceResultType};
+
+    match result.r#type {
+        InferenceResultType::Data => {
+            if let Some(data) = result.data {
+                send(CompletionEvent::Token(data.token));
+            }
+        }
+        InferenceResultType::Error => {
+            send(CompletionEvent::Error(result.message.unwrap_or_default()))
+        }
+        InferenceResultType::Progress => {}
+        InferenceResultType::End => {
+            let usage = result.usage.unwrap_or_default();
+            let finish_reason = match result.finish_reason {
+                Some(LLamaFinishReason::Eos) | Some(LLamaFinishReason::StopSequence) => {
+                    FinishReason::Stop
+                }
+                Some(LLamaFinishReason::Length) => FinishReason::Length,
+                Some(LLamaFinishReason::ContextFull) => {
+                    return send_error(send, context_full_error(result.message));
+                }
+                Some(LLamaFinishReason::Aborted) => FinishReason::Aborted,
+                Some(LLamaFinishReason::Error) | None => FinishReason::Error,
+            };
+            send(CompletionEvent::End {
+                finish_reason,
+                prompt_tokens: usage.n_prompt_tokens as u32,
+                completion_tokens: usage.n_generated_tokens as u32,
+            });
+        }
+    }
+}
+
+fn send_rwkv_result(send: &impl Fn(CompletionEvent), result: rwkv_types::InferenceResult) {
+    use rwkv_types::{FinishReason as RwkvFinishReason, InferenceResultType};
+
+    match result.r#type {
+        InferenceResultType::Data => send(CompletionEvent::Token(
+            result.data.map(|data| data.token).unwrap_or_default(),
+        )),
+        InferenceResultType::Error => {
+            send(CompletionEvent::Error(result.message.unwrap_or_default()))
+        }
+        InferenceResultType::End => {
+            let finish_reason = match result.finish_reason {
+                Some(RwkvFinishReason::Eos) | Some(RwkvFinishReason::StopSequence) => {
+                    FinishReason::Stop
+                }
+                Some(RwkvFinishReason::Length) => FinishReason::Length,
+                Some(RwkvFinishReason::ContextFull) => {
+                    return send_error(send, context_full_error(result.message));
+                }
+                Some(RwkvFinishReason::Aborted) => FinishReason::Aborted,
+                Some(RwkvFinishReason::Error) | None => FinishReason::Error,
+            };
+            send(CompletionEvent::End {
+                finish_reason,
+                prompt_tokens: result
+                    .usage
+                    .as_ref()
+                    .map_or(0, |usage| usage.n_prompt_tokens),
+                completion_tokens: result
+                    .usage
+                    .as_ref()
+                    .map_or(0, |usage| usage.n_generated_tokens),
+            });
+        }
+    }
+}
@@ -0,0 +1,363 @@
+use std::{
+    convert::Infallible,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{rejection::JsonRejection, State},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, post},
+    Json, Router,
+};
+use futures::{stream, Stream, StreamExt};
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::{
+    error::ApiError,
+    model::{Completion, CompletionEvent, FinishReason, Model},
+    openai::{
+        ChatCompletionChoice, ChatCompletionChunk, ChatCompletionChunkChoice, ChatCompletionDelta,
+        ChatCompletionMessage, ChatCompletionRequest, ChatCompletionResponse, CompletionChoice,
+        CompletionRequest, CompletionResponse, Embedding, EmbeddingRequest, EmbeddingResponse,
+        EmbeddingUsage, ModelList, ModelObject, SamplingParams, Usage,
+    },
+};
+
+pub struct AppState {
+    pub models: Vec<Model>,
+    // Reported as the `created` time of every model.
+    pub started_at: u64,
+}
+
+impl AppState {
+    fn model(&self, id: &str) -> Result<&Model, ApiError> {
+        self.models
+            .iter()
+            .find(|model| model.id == id)
+            .ok_or_else(|| ApiError::model_not_found(id))
+    }
+}
+
+type AppStateRef = State<Arc<AppState>>;
+
+pub fn router(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/v1/models", get(list_models))
+        .route("/v1/completions", post(completions))
+        .route("/v1/chat/completions", post(chat_completions))
+        .route("/v1/embeddings", post(embeddings))
+        .with_state(state)
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0)
+}
+
+fn new_id(prefix: &str) -> String {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    format!(
+        "{}-{}-{}",
+        prefix,
+        unix_time(),
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+fn to_completion(
+    prompt: String,
+    sampling: SamplingParams,
+    mut stop: Vec<String>,
+) -> Result<Completion, ApiError> {
+    if sampling.n.unwrap_or(1) != 1 {
+        return Err(ApiError::invalid_request("Only n = 1 is supported"));
+    }
+    if let Some(request_stop) = sampling.stop {
+        stop.extend(request_stop.into_vec());
+    }
+
+    Ok(Completion {
+        prompt,
+        max_tokens: sampling.max_tokens,
+        temperature: sampling.temperature,
+        top_p: sampling.top_p,
+        presence_penalty: sampling.presence_penalty,
+        frequency_penalty: sampling.frequency_penalty,
+        seed: sampling.seed,
+        stop,
+    })
+}
+
+struct CompletionOutput {
+    text: String,
+    finish_reason: FinishReason,
+    usage: Usage,
+}
+
+// Waits for the End event, a failed generation is returned as an error.
+async fn collect(
+    mut receiver: mpsc::Receiver<CompletionEvent>,
+) -> Result<CompletionOutput, ApiError> {
+    let mut text = String::new();
+    let mut error = None;
+
+    while let Some(event) = receiver.recv().await {
+        match event {
+            CompletionEvent::Token(token) => text.push_str(&token),
+            CompletionEvent::Error(message) => error = Some(message),
+            CompletionEvent::End {
+                finish_reason,
+                prompt_tokens,
+                completion_tokens,
+            } => {
+                if let Some(message) = error {
+                    return Err(ApiError::from_message(&message));
+                }
+                return Ok(CompletionOutput {
+                    text,
+                    finish_reason,
+                    usage: Usage {
+                        prompt_tokens,
+                        completion_tokens,
+                        total_tokens: prompt_tokens + completion_tokens,
+                    },
+                });
+            }
+        }
+    }
+
+    Err(ApiError::from_message(
+        "The generation ended without a result",
+    ))
+}
+
+fn json_event(value: impl Serialize) -> Event {
+    Event::default().json_data(value).unwrap_or_default()
+}
+
+// Streams the inference events as server-sent events, `to_event` turns a token or the end of
+// the generation into a chunk. The stream ends with `[DONE]`, or with an error object when the
+// generation failed. Dropping the stream aborts the generation.
+fn sse(
+    first: Option<Event>,
+    receiver: mpsc::Receiver<CompletionEvent>,
+    mut to_event: impl FnMut(Option<String>, Option<FinishReason>) -> Option<Event> + Send + 'static,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events = stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|event| (event, receiver))
+    })
+    .flat_map(move |event| {
+        let events = match event {
+            CompletionEvent::Token(token) => to_event(Some(token), None).into_iter().collect(),
+            CompletionEvent::Error(message) => {
+                vec![json_event(ApiError::from_message(&message).body())]
+            }
+            CompletionEvent::End { finish_reason, .. } => match finish_reason.as_openai() {
+                Some(_) => to_event(None, Some(finish_reason))
+                    .into_iter()
+                    .chain([Event::default().data("[DONE]")])
+                    .collect(),
+                None => vec![],
+            },
+        };
+        stream::iter(events)
+    });
+
+    Sse::new(stream::iter(first).chain(events).map(Ok)).keep_alive(KeepAlive::default())
+}
+
+async fn list_models(State(state): AppStateRef) -> Json<ModelList> {
+    Json(ModelList {
+        object: "list",
+        data: state
+            .models
+            .iter()
+            .map(|model| ModelObject {
+                id: model.id.clone(),
+                object: "model",
+                created: state.started_at,
+                owned_by: "llama-node",
+            })
+            .collect(),
+    })
+}
+
+async fn completions(
+    State(state): AppStateRef,
+    request: Result<Json<CompletionRequest>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let Json(request) = request?;
+    let model = state.model(&request.model)?;
+
+    let mut prompts = request.prompt.into_vec();
+    if prompts.len() != 1 {
+        return Err(ApiError::invalid_request(
+            "Only a single prompt is supported",
+        ));
+    }
+    let stream = request.sampling.stream;
+    let completion = to_completion(prompts.remove(0), request.sampling, vec![])?;
+    let receiver = model.complete(completion);
+
+    let id = new_id("cmpl");
+    let created = unix_time();
+    let model_id = model.id.clone();
+
+    if stream {
+        return Ok(sse(None, receiver, move |text, finish_reason| {
+            Some(json_event(CompletionResponse {
+                id: id.clone(),
+                object: "text_completion",
+                created,
+                model: model_id.clone(),
+                choices: vec![CompletionChoice {
+                    index: 0,
+                    text: text.unwrap_or_default(),
+                    logprobs: None,
+                    finish_reason: finish_reason.and_then(|reason| reason.as_openai()),
+                }],
+                usage: None,
+            }))
+        })
+        .into_response());
+    }
+
+    let output = collect(receiver).await?;
+    Ok(Json(CompletionResponse {
+        id,
+        object: "text_completion",
+        created,
+        model: model_id,
+        choices: vec![CompletionChoice {
+            index: 0,
+            text: output.text,
+            logprobs: None,
+            finish_reason: output.finish_reason.as_openai(),
+        }],
+        usage: Some(output.usage),
+    })
+    .into_response())
+}
+
+async fn chat_completions(
+    State(state): AppStateRef,
+    request: Result<Json<ChatCompletionRequest>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let Json(request) = request?;
+    let model = state.model(&request.model)?;
+
+    let prompt = model
+        .chat
+        .render(&request.messages)
+        .map_err(ApiError::invalid_request)?;
+    let stream = request.sampling.stream;
+    let completion = to_completion(prompt, request.sampling, vec![model.chat.stop_sequence()])?;
+    let receiver = model.complete(completion);
+
+    let id = new_id("chatcmpl");
+    let created = unix_time();
+    let model_id = model.id.clone();
+
+    if stream {
+        let chunk = move |delta: ChatCompletionDelta, finish_reason: Option<&'static str>| {
+            json_event(ChatCompletionChunk {
+                id: id.clone(),
+                object: "chat.completion.chunk",
+                created,
+                model: model_id.clone(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 0,
+                    delta,
+                    finish_reason,
+                }],
+            })
+        };
+        let first = chunk(
+            ChatCompletionDelta {
+                role: Some("assistant"),
+                content: None,
+            },
+            None,
+        );
+        // The prompt ends with the assistant prefix, the whitespace after it is dropped.
+        let mut started = false;
+
+        return Ok(sse(Some(first), receiver, move |text, finish_reason| {
+            let content = match text {
+                Some(text) if !started => {
+                    let text = text.trim_start();
+                    if text.is_empty() {
+                        return None;
+                    }
+                    started = true;
+                    Some(text.to_string())
+                }
+                text => text,
+            };
+            Some(chunk(
+                ChatCompletionDelta {
+                    role: None,
+                    content,
+                },
+                finish_reason.and_then(|reason| reason.as_openai()),
+            ))
+        })
+        .into_response());
+    }
+
+    let output = collect(receiver).await?;
+    Ok(Json(ChatCompletionResponse {
+        id,
+        object: "chat.completion",
+        created,
+        model: model_id,
+        choices: vec![ChatCompletionChoice {
+            index: 0,
+            message: ChatCompletionMessage {
+                role: "assistant",
+                content: output.text.trim().to_string(),
+            },
+            finish_reason: output.finish_reason.as_openai(),
+        }],
+        usage: output.usage,
+    })
+    .into_response())
+}
+
+async fn embeddings(
+    State(state): AppStateRef,
+    request: Result<Json<EmbeddingRequest>, JsonRejection>,
+) -> Result<Json<EmbeddingResponse>, ApiError> {
+    let Json(request) = request?;
+    let model = state.model(&request.model)?;
+
+    let (embeddings, n_tokens) = model.embed(request.input.into_vec()).await?;
+
+    Ok(Json(EmbeddingResponse {
+        object: "list",
+        data: embeddings
+            .into_iter()
+            .enumerate()
+            .map(|(index, embedding)| Embedding {
+                object: "embedding",
+                index: index as u32,
+                embedding,
+            })
+            .collect(),
+        model: model.id.clone(),
+        usage: EmbeddingUsage {
+            prompt_tokens: n_tokens,
+            total_tokens: n_tokens,
+        },
+    }))
+}
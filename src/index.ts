@@ -1,5 +1,12 @@
-import type { ILLM, CompletionCallback, LLMResult } from "./llm/type";
+import type {
+    ILLM,
+    CompletionCallback,
+    EmbeddingProgressCallback,
+    LLMResult,
+    LLMStreamResult,
+} from "./llm/type";
 export type * from "./llm/type";
+export * from "./llm/error";
 
 class LLM<
     Instance = any,
@@ -28,8 +35,8 @@ class LLM<
         this.llm = new llm();
     }
 
-    load(config: LoadConfig) {
-        return this.llm.load(config);
+    load(config: LoadConfig, abortSignal?: AbortSignal) {
+        return this.llm.load(config, abortSignal);
     }
 
     async createCompletion(
@@ -40,30 +47,77 @@ class LLM<
         return this.llm.createCompletion(params, callback, abortSignal);
     }
 
-    async getEmbedding(params: LLMEmbeddingArguments): Promise<number[]> {
+    createCompletionStream(
+        params: LLMInferenceArguments,
+        abortSignal?: AbortSignal,
+        bufferSize?: number
+    ): AsyncIterable<LLMStreamResult> {
+        if (!this.llm.createCompletionStream) {
+            throw new Error(
+                "createCompletionStream not implemented for current LLM"
+            );
+        }
+        return this.llm.createCompletionStream(params, abortSignal, bufferSize);
+    }
+
+    async getEmbedding(
+        params: LLMEmbeddingArguments,
+        abortSignal?: AbortSignal
+    ): Promise<number[]> {
         if (!this.llm.getEmbedding) {
             console.warn("getEmbedding not implemented for current LLM");
             return [];
         } else {
-            return this.llm.getEmbedding(params);
+            return this.llm.getEmbedding(params, abortSignal);
         }
     }
 
-    async getDefaultEmbeddings(text: string): Promise<number[]> {
+    async getDefaultEmbeddings(
+        text: string,
+        abortSignal?: AbortSignal
+    ): Promise<number[]> {
         if (!this.llm.getDefaultEmbedding) {
             console.warn("getDefaultEmbedding not implemented for current LLM");
             return [];
         } else {
-            return this.llm.getDefaultEmbedding(text);
+            return this.llm.getDefaultEmbedding(text, abortSignal);
         }
     }
 
-    async tokenize(content: TokenizeArguments): Promise<number[]> {
+    async getEmbeddings(
+        texts: string[],
+        onProgress?: EmbeddingProgressCallback,
+        abortSignal?: AbortSignal
+    ): Promise<number[][]> {
+        if (!this.llm.getEmbeddings) {
+            console.warn("getEmbeddings not implemented for current LLM");
+            return [];
+        } else {
+            return this.llm.getEmbeddings(texts, onProgress, abortSignal);
+        }
+    }
+
+    async tokenize(
+        content: TokenizeArguments,
+        abortSignal?: AbortSignal
+    ): Promise<number[]> {
         if (!this.llm.tokenize) {
             console.warn("tokenize not implemented for current LLM");
             return [];
         } else {
-            return this.llm.tokenize(content);
+            return this.llm.tokenize(content, abortSignal);
+        }
+    }
+
+    /**
+     * Frees the native model once the running calls finish, without waiting for the garbage
+     * collector. Calls made after it reject.
+     */
+    async dispose(): Promise<void> {
+        if (!this.llm.dispose) {
+            console.warn("dispose not implemented for current LLM");
+        } else {
+            return this.llm.dispose();
         }
     }
 }
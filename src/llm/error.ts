@@ -0,0 +1,107 @@
+/** Codes of the errors thrown by the native backends. */
+export enum LLMErrorCode {
+    InvalidParam = "InvalidParam",
+    ModelLoadFailed = "ModelLoadFailed",
+    ContextFull = "ContextFull",
+    Aborted = "Aborted",
+    Disposed = "Disposed",
+    InferenceFailed = "InferenceFailed",
+    WorkerCrashed = "WorkerCrashed",
+}
+
+/** Base class of the errors thrown by the native backends. */
+export class LLMNativeError extends Error {
+    public readonly code: LLMErrorCode;
+
+    constructor(code: LLMErrorCode, message: string) {
+        super(message);
+        this.name = new.target.name;
+        this.code = code;
+    }
+}
+
+/** A parameter is missing, has the wrong type or an invalid value. */
+export class InvalidParamError extends LLMNativeError {
+    constructor(message: string) {
+        super(LLMErrorCode.InvalidParam, message);
+    }
+}
+
+/** The model, its tokenizer or an adapter could not be loaded. */
+export class ModelLoadError extends LLMNativeError {
+    constructor(message: string) {
+        super(LLMErrorCode.ModelLoadFailed, message);
+    }
+}
+
+/** The input does not fit in the context window. */
+export class ContextFullError extends LLMNativeError {
+    constructor(message: string) {
+        super(LLMErrorCode.ContextFull, message);
+    }
+}
+
+/** The call was aborted through its `AbortSignal`. */
+export class AbortedError extends LLMNativeError {
+    constructor(message: string) {
+        super(LLMErrorCode.Aborted, message);
+    }
+}
+
+/** The model was disposed before or while the call ran. */
+export class DisposedError extends LLMNativeError {
+    constructor(message: string) {
+        super(LLMErrorCode.Disposed, message);
+    }
+}
+
+/** The backend failed while evaluating the input. */
+export class InferenceFailedError extends LLMNativeError {
+    constructor(message: string) {
+        super(LLMErrorCode.InferenceFailed, message);
+    }
+}
+
+/** The worker process running the model exited while the call was pending. */
+export class WorkerCrashedError extends LLMNativeError {
+    constructor(message: string) {
+        super(LLMErrorCode.WorkerCrashed, message);
+    }
+}
+
+type LLMNativeErrorClass = new (message: string) => LLMNativeError;
+
+const errorClasses: Record<LLMErrorCode, LLMNativeErrorClass> = {
+    [LLMErrorCode.InvalidParam]: InvalidParamError,
+    [LLMErrorCode.ModelLoadFailed]: ModelLoadError,
+    [LLMErrorCode.ContextFull]: ContextFullError,
+    [LLMErrorCode.Aborted]: AbortedError,
+    [LLMErrorCode.Disposed]: DisposedError,
+    [LLMErrorCode.InferenceFailed]: InferenceFailedError,
+    [LLMErrorCode.WorkerCrashed]: WorkerCrashedError,
+};
+
+/**
+ * The native backends prefix their error messages with `[Code] `, this turns
+ * such an error into the matching `LLMNativeError` subclass. Other errors are
+ * returned untouched.
+ */
+export const toLLMError = (error: unknown): unknown => {
+    const message = error instanceof Error ? error.message : undefined;
+    const match = message?.match(/^\[(\w+)\] ([\s\S]*)$/);
+    const ErrorClass = match && errorClasses[match[1] as LLMErrorCode];
+
+    if (!match || !ErrorClass) {
+        return error;
+    }
+
+    const converted = new ErrorClass(match[2]);
+    converted.stack = (error as Error).stack;
+    return converted;
+};
+
+/** Converts the native error `promise` may reject with. */
+export const withLLMError = <T>(promise: Promise<T>): Promise<T> =>
+    promise.catch((error) => {
+        throw toLLMError(error);
+    });
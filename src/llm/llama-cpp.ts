@@ -1,11 +1,36 @@
 import {
+    FinishReason,
+    InferenceResult,
     InferenceResultType,
     LLama,
+    LlamaLoraAdaptor,
     ModelLoad,
     Generate,
 } from "@llama-node/llama-cpp";
 
-import { type ILLM, type LLMResult, LLMError, LLMErrorType } from "./type";
+import {
+    type CompletionCallback,
+    type EmbeddingProgressCallback,
+    type ILLM,
+    type LLMResult,
+} from "./type";
+import {
+    AbortedError,
+    InferenceFailedError,
+    LLMNativeError,
+    toLLMError,
+    withLLMError,
+} from "./error";
+import { iterateInferenceStream } from "./stream";
+
+const defaultEmbeddingParams: Omit<Generate, "prompt"> = {
+    nThreads: 4,
+    nTokPredict: 1024,
+    topK: 40,
+    topP: 0.1,
+    temp: 0.1,
+    repeatPenalty: 1,
+};
 
 export interface LoadConfig extends ModelLoad {
     enableLogging: boolean;
@@ -16,95 +41,152 @@ export class LLamaCpp
 {
     instance!: LLama;
 
-    async load(config: LoadConfig) {
+    async load(config: LoadConfig, abortSignal?: AbortSignal) {
         const { enableLogging, ...rest } = config;
-        this.instance = await LLama.load(rest, enableLogging);
+        this.instance = await withLLMError(
+            LLama.load(rest, enableLogging, abortSignal)
+        );
+    }
+
+    async applyLora(params: LlamaLoraAdaptor) {
+        await withLLMError(this.instance.applyLora(params));
     }
 
     async createCompletion(
         params: Generate,
-        callback: (data: { token: string; completed: boolean }) => void,
+        callback: CompletionCallback,
         abortSignal?: AbortSignal
     ): Promise<LLMResult> {
+        if (abortSignal?.aborted) {
+            throw new AbortedError("Aborted");
+        }
+
         let completed = false;
         const tokens: string[] = [];
         const errors: string[] = [];
         return new Promise<LLMResult>(
-            (res, rej: (reason: LLMError) => void) => {
-                const abort = this.instance.inference(params, (response) => {
+            (res, rej: (reason: unknown) => void) => {
+                const onResult = (response: InferenceResult) => {
                     switch (response.type) {
                         case InferenceResultType.Data: {
-                            const data = {
-                                token: response.data!.token,
-                                completed: !!response.data!.completed,
-                            };
+                            const data = { token: response.data!.token };
                             tokens.push(data.token);
-                            if (data.completed) {
-                                completed = true;
-                            }
                             callback(data);
                             break;
                         }
                         case InferenceResultType.End: {
+                            removeAbortListener();
+                            completed =
+                                response.finishReason === FinishReason.Eos ||
+                                response.finishReason ===
+                                    FinishReason.StopSequence;
                             if (errors.length) {
+                                const message = errors.join("\n");
+                                const error = toLLMError(new Error(message));
                                 rej(
-                                    new LLMError({
-                                        message: errors.join("\n"),
-                                        tokens,
-                                        completed,
-                                        type: LLMErrorType.Generic,
-                                    })
+                                    error instanceof LLMNativeError
+                                        ? error
+                                        : new InferenceFailedError(message)
                                 );
                             } else {
-                                res({ tokens, completed });
+                                res({
+                                    tokens,
+                                    completed,
+                                    finishReason: response.finishReason,
+                                    text: response.text,
+                                    usage: response.usage,
+                                });
                             }
                             break;
                         }
                         case InferenceResultType.Error: {
                             errors.push(response.message ?? "Unknown Error");
+                            removeAbortListener();
                             break;
                         }
                     }
-                });
+                };
+
+                let abort: () => void;
+                try {
+                    abort = this.instance.inference(params, onResult);
+                } catch (error) {
+                    rej(toLLMError(error));
+                    return;
+                }
 
                 const abortSignalHandler = () => {
                     abort();
-                    rej(
-                        new LLMError({
-                            message: "Aborted",
-                            tokens,
-                            completed,
-                            type: LLMErrorType.Aborted,
-                        })
-                    );
+                    removeAbortListener();
+                    rej(new AbortedError("Aborted"));
+                };
+                const removeAbortListener = () =>
                     abortSignal?.removeEventListener(
                         "abort",
                         abortSignalHandler
                     );
-                };
 
                 abortSignal?.addEventListener("abort", abortSignalHandler);
             }
         );
     }
 
-    async getEmbedding(params: Generate): Promise<number[]> {
-        return await this.instance.getWordEmbedding(params);
+    createCompletionStream(
+        params: Generate,
+        abortSignal?: AbortSignal,
+        bufferSize?: number
+    ): AsyncGenerator<InferenceResult, void, undefined> {
+        return iterateInferenceStream(
+            this.instance.inferenceStream(params, bufferSize, abortSignal)
+        );
+    }
+
+    async getEmbedding(
+        params: Generate,
+        abortSignal?: AbortSignal
+    ): Promise<number[]> {
+        return await withLLMError(
+            this.instance.getWordEmbedding(params, abortSignal)
+        );
+    }
+
+    async getDefaultEmbedding(
+        text: string,
+        abortSignal?: AbortSignal
+    ): Promise<number[]> {
+        return this.getEmbedding(
+            {
+                ...defaultEmbeddingParams,
+                prompt: text,
+            },
+            abortSignal
+        );
     }
 
-    async getDefaultEmbedding(text: string): Promise<number[]> {
-        return this.getEmbedding({
-            nThreads: 4,
-            nTokPredict: 1024,
-            topK: 40,
-            topP: 0.1,
-            temp: 0.1,
-            repeatPenalty: 1,
-            prompt: text,
-        });
+    async getEmbeddings(
+        texts: string[],
+        onProgress?: EmbeddingProgressCallback,
+        abortSignal?: AbortSignal,
+        params: Omit<Generate, "prompt"> = defaultEmbeddingParams
+    ): Promise<number[][]> {
+        return await withLLMError(
+            this.instance.getEmbeddings(
+                texts,
+                { ...params, prompt: "" },
+                onProgress,
+                abortSignal
+            )
+        );
+    }
+
+    async tokenize(
+        params: string,
+        abortSignal?: AbortSignal
+    ): Promise<number[]> {
+        return await withLLMError(this.instance.tokenize(params, abortSignal));
     }
 
-    async tokenize(params: string): Promise<number[]> {
-        return await this.instance.tokenize(params);
+    async dispose(): Promise<void> {
+        await withLLMError(this.instance.dispose());
     }
 }
@@ -1,16 +1,30 @@
 import {
-    // EmbeddingResultType,
+    FinishReason,
+    InferenceResult,
     InferenceResultType,
+    ModelLoad,
     Rwkv,
     RwkvInvocation,
 } from "@llama-node/rwkv-cpp";
 
-import { type ILLM, type LLMResult, LLMError, LLMErrorType } from "./type";
+import {
+    type CompletionCallback,
+    type EmbeddingProgressCallback,
+    type ILLM,
+    type LLMResult,
+} from "./type";
+import {
+    AbortedError,
+    InferenceFailedError,
+    LLMNativeError,
+    toLLMError,
+    withLLMError,
+} from "./error";
+import { iterateInferenceStream } from "./stream";
 
-export interface LoadConfig {
+export interface LoadConfig extends Partial<Omit<ModelLoad, "tokenizerJson">> {
     modelPath: string;
-    tokenizerPath: string;
-    nThreads: number;
+    tokenizerJson?: string | Buffer;
     enableLogging: boolean;
 }
 
@@ -18,116 +32,177 @@ export interface TokenizeArguments {
     content: string;
 }
 
+export interface EmbeddingArguments {
+    content: string;
+    normalize?: boolean;
+}
+
 export class RwkvCpp
     implements
-        ILLM<Rwkv, LoadConfig, RwkvInvocation, unknown, TokenizeArguments>
+        ILLM<
+            Rwkv,
+            LoadConfig,
+            RwkvInvocation,
+            EmbeddingArguments,
+            TokenizeArguments
+        >
 {
     instance!: Rwkv;
 
-    async load(config: LoadConfig) {
-        const { modelPath, tokenizerPath, nThreads, enableLogging } = config;
-        this.instance = await Rwkv.load(
-            modelPath,
-            tokenizerPath,
-            nThreads,
-            enableLogging
+    async load(config: LoadConfig, abortSignal?: AbortSignal) {
+        const { enableLogging, tokenizerJson, ...rest } = config;
+        this.instance = await withLLMError(
+            Rwkv.load(
+                {
+                    ...rest,
+                    tokenizerJson: Buffer.isBuffer(tokenizerJson)
+                        ? tokenizerJson.toString("utf8")
+                        : tokenizerJson,
+                },
+                enableLogging,
+                abortSignal
+            )
         );
     }
 
     async createCompletion(
         params: RwkvInvocation,
-        callback: (data: { token: string; completed: boolean }) => void,
+        callback: CompletionCallback,
         abortSignal?: AbortSignal
     ): Promise<LLMResult> {
+        if (abortSignal?.aborted) {
+            throw new AbortedError("Aborted");
+        }
+
         let completed = false;
         const tokens: string[] = [];
         const errors: string[] = [];
         return new Promise<LLMResult>(
-            (res, rej: (reason: LLMError) => void) => {
-                const abort = this.instance.inference(params, (response) => {
+            (res, rej: (reason: unknown) => void) => {
+                const onResult = (response: InferenceResult) => {
                     switch (response.type) {
                         case InferenceResultType.Data: {
-                            const data = {
-                                token: response.data!.token,
-                                completed: !!response.data!.completed,
-                            };
+                            const data = { token: response.data!.token };
                             tokens.push(data.token);
-                            if (data.completed) {
-                                completed = true;
-                            }
                             callback(data);
                             break;
                         }
                         case InferenceResultType.End: {
+                            removeAbortListener();
+                            completed =
+                                response.finishReason === FinishReason.Eos ||
+                                response.finishReason ===
+                                    FinishReason.StopSequence;
                             if (errors.length) {
+                                const message = errors.join("\n");
+                                const error = toLLMError(new Error(message));
                                 rej(
-                                    new LLMError({
-                                        message: errors.join("\n"),
-                                        tokens,
-                                        completed,
-                                        type: LLMErrorType.Generic,
-                                    })
+                                    error instanceof LLMNativeError
+                                        ? error
+                                        : new InferenceFailedError(message)
                                 );
                             } else {
-                                res({ tokens, completed });
+                                res({
+                                    tokens,
+                                    completed,
+                                    finishReason: response.finishReason,
+                                    text: response.text,
+                                    usage: response.usage,
+                                });
                             }
                             break;
                         }
                         case InferenceResultType.Error: {
                             errors.push(response.message ?? "Unknown Error");
+                            removeAbortListener();
                             break;
                         }
                     }
-                });
+                };
+
+                let abort: () => void;
+                try {
+                    abort = this.instance.inference(params, onResult);
+                } catch (error) {
+                    rej(toLLMError(error));
+                    return;
+                }
 
                 const abortSignalHandler = () => {
                     abort();
-                    rej(
-                        new LLMError({
-                            message: "Aborted",
-                            tokens,
-                            completed,
-                            type: LLMErrorType.Aborted,
-                        })
-                    );
+                    removeAbortListener();
+                    rej(new AbortedError("Aborted"));
+                };
+                const removeAbortListener = () =>
                     abortSignal?.removeEventListener(
                         "abort",
                         abortSignalHandler
                     );
-                };
 
                 abortSignal?.addEventListener("abort", abortSignalHandler);
             }
         );
     }
 
-    // embedding not implemented yet
-
-    /* async getEmbedding(params: RwkvInvocation): Promise<number[]> {
-        return new Promise<number[]>((res, rej) => {
-            this.instance.getWordEmbedding(params, (response) => {
-                switch (response.type) {
-                    case EmbeddingResultType.Data:
-                        res(response.data ?? []);
-                        break;
-                    case EmbeddingResultType.Error:
-                        rej(new Error("Unknown Error"));
-                        break;
-                }
-            });
-        });
+    createCompletionStream(
+        params: RwkvInvocation,
+        abortSignal?: AbortSignal,
+        bufferSize?: number
+    ): AsyncGenerator<InferenceResult, void, undefined> {
+        return iterateInferenceStream(
+            this.instance.inferenceStream(params, bufferSize, abortSignal)
+        );
+    }
+
+    async getEmbedding(
+        params: EmbeddingArguments,
+        abortSignal?: AbortSignal
+    ): Promise<number[]> {
+        return await withLLMError(
+            this.instance.getEmbedding(
+                params.content,
+                params.normalize,
+                abortSignal
+            )
+        );
+    }
+
+    async getDefaultEmbedding(
+        text: string,
+        abortSignal?: AbortSignal
+    ): Promise<number[]> {
+        return this.getEmbedding(
+            { content: text, normalize: true },
+            abortSignal
+        );
+    }
+
+    async getEmbeddings(
+        texts: string[],
+        onProgress?: EmbeddingProgressCallback,
+        abortSignal?: AbortSignal,
+        normalize = true
+    ): Promise<number[][]> {
+        return await withLLMError(
+            this.instance.getEmbeddings(
+                texts,
+                normalize,
+                onProgress,
+                abortSignal
+            )
+        );
+    }
+
+    async tokenize(
+        params: TokenizeArguments,
+        abortSignal?: AbortSignal
+    ): Promise<number[]> {
+        return await withLLMError(
+            this.instance.tokenize(params.content, abortSignal)
+        );
     }
 
-    async getDefaultEmbedding(text: string): Promise<number[]> {
-        return this.getEmbedding({
-            topP: 0.1,
-            temp: 0.1,
-            prompt: text,
-            maxPredictLength: 2048,
-        });
-    } */
-
-    async tokenize(params: TokenizeArguments): Promise<number[]> {
-        return await this.instance.tokenize(params.content);
+    async dispose(): Promise<void> {
+        await withLLMError(this.instance.dispose());
     }
 }
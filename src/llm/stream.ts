@@ -0,0 +1,25 @@
+export interface NativeInferenceStream<T> {
+    next(): Promise<T | null>;
+    abort(): void;
+}
+
+/**
+ * Iterates a native inference stream with `for await`, or pipes it through `Readable.from`.
+ * The native side only generates ahead of the consumer up to its buffer size. Generation
+ * stops when the signal passed to `inferenceStream` is aborted or when the loop exits early.
+ */
+export async function* iterateInferenceStream<T>(
+    stream: NativeInferenceStream<T>
+): AsyncGenerator<T, void, undefined> {
+    try {
+        for (
+            let result = await stream.next();
+            result !== null;
+            result = await stream.next()
+        ) {
+            yield result;
+        }
+    } finally {
+        stream.abort();
+    }
+}
@@ -1,5 +1,9 @@
 export interface CompletionCallback {
-    (data: { token: string; completed: boolean }): void;
+    (data: { token: string }): void;
+}
+
+export interface EmbeddingProgressCallback {
+    (progress: { nProcessed: number; nTotal: number }): void;
 }
 
 export interface ILLM<
@@ -11,7 +15,7 @@ export interface ILLM<
 > {
     readonly instance: Instance;
 
-    load(config: LoadConfig): Promise<void>;
+    load(config: LoadConfig, abortSignal?: AbortSignal): Promise<void>;
 
     createCompletion(
         params: LLMInferenceArguments,
@@ -19,42 +23,52 @@ export interface ILLM<
         abortSignal?: AbortSignal
     ): Promise<LLMResult>;
 
-    getEmbedding?(params: LLMEmbeddingArguments): Promise<number[]>;
+    createCompletionStream?(
+        params: LLMInferenceArguments,
+        abortSignal?: AbortSignal,
+        bufferSize?: number
+    ): AsyncIterable<LLMStreamResult>;
 
-    getDefaultEmbedding?(text: string): Promise<number[]>;
+    getEmbedding?(
+        params: LLMEmbeddingArguments,
+        abortSignal?: AbortSignal
+    ): Promise<number[]>;
 
-    tokenize?(content: LLMTokenizeArguments): Promise<number[]>;
-}
+    getDefaultEmbedding?(
+        text: string,
+        abortSignal?: AbortSignal
+    ): Promise<number[]>;
 
-export interface LLMResult {
-    tokens: string[];
-    completed: boolean;
+    getEmbeddings?(
+        texts: string[],
+        onProgress?: EmbeddingProgressCallback,
+        abortSignal?: AbortSignal
+    ): Promise<number[][]>;
+
+    tokenize?(
+        content: LLMTokenizeArguments,
+        abortSignal?: AbortSignal
+    ): Promise<number[]>;
+
+    dispose?(): Promise<void>;
 }
 
-export enum LLMErrorType {
-    Aborted = "Aborted",
-    Generic = "Generic",
+/** The fields shared by the inference results of every backend. */
+export interface LLMStreamResult {
+    type: string;
+    data?: { token: string };
+    finishReason?: string;
+    text?: string;
+    message?: string;
 }
 
-export class LLMError extends Error {
-    public readonly tokens: string[];
-    public readonly completed: boolean;
-    public readonly type: LLMErrorType;
-
-    constructor({
-        message,
-        tokens,
-        completed,
-        type,
-    }: {
-        message: string;
-        tokens: string[];
-        completed: boolean;
-        type: LLMErrorType;
-    }) {
-        super(message);
-        this.tokens = tokens;
-        this.completed = completed;
-        this.type = type;
-    }
+export interface LLMResult {
+    tokens: string[];
+    /** The generation ended on the end of sequence token or a stop sequence */
+    completed: boolean;
+    /** Why the generation ended, as reported by the backend */
+    finishReason?: string;
+    /** The full generated text */
+    text?: string;
+    usage?: { nPromptTokens: number; nGeneratedTokens: number };
 }
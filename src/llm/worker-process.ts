@@ -0,0 +1,142 @@
+import path from "path";
+
+import { LLMNativeError } from "./error";
+import type { ILLM } from "./type";
+import type {
+    WorkerBackend,
+    WorkerRequest,
+    WorkerResponse,
+    SerializedError,
+} from "./worker";
+
+// Entry of the child process forked by `LLMWorker`, the backend is given as
+// the first argument.
+
+const backendExports: Record<WorkerBackend, string> = {
+    "llama-cpp": "LLamaCpp",
+    "rwkv-cpp": "RwkvCpp",
+    "llm-rs": "LLMRS",
+};
+
+const send = (response: WorkerResponse) => process.send?.(response);
+
+const serializeError = (error: unknown): SerializedError => {
+    if (error instanceof LLMNativeError) {
+        // Same message format as the native errors, the host converts it back.
+        return { message: `[${error.code}] ${error.message}` };
+    }
+    return { message: error instanceof Error ? error.message : String(error) };
+};
+
+// Stream results sent to the host and not acked yet, `wake` resumes the
+// stream paused on a full window.
+interface StreamFlow {
+    inFlight: number;
+    wake?: () => void;
+}
+
+const handle = async (
+    llm: ILLM<unknown, any, any, any, any>,
+    request: Extract<WorkerRequest, { type: "call" }>,
+    signal: AbortSignal,
+    flow: StreamFlow
+) => {
+    const { id, method, args } = request;
+    const emit = (value: unknown) => send({ id, type: "callback", value });
+
+    switch (method) {
+        case "load":
+            return llm.load(args[0], signal);
+        case "createCompletion":
+            return llm.createCompletion(args[0], emit, signal);
+        case "createCompletionStream": {
+            if (!llm.createCompletionStream) {
+                throw new Error("createCompletionStream not implemented");
+            }
+            const window = Math.max(args[1] ?? 16, 1);
+            for await (const result of llm.createCompletionStream(
+                args[0],
+                signal,
+                args[1]
+            )) {
+                emit(result);
+                flow.inFlight++;
+                // Pausing here lets the native buffer fill up, which pauses
+                // the generation.
+                while (flow.inFlight >= window && !signal.aborted) {
+                    await new Promise<void>((resolve) => (flow.wake = resolve));
+                }
+            }
+            return;
+        }
+        case "getEmbedding":
+            return llm.getEmbedding?.(args[0], signal);
+        case "getDefaultEmbedding":
+            return llm.getDefaultEmbedding?.(args[0], signal);
+        case "getEmbeddings":
+            return llm.getEmbeddings?.(args[0], emit, signal);
+        case "tokenize":
+            return llm.tokenize?.(args[0], signal);
+        case "dispose":
+            return llm.dispose?.();
+    }
+};
+
+const main = async () => {
+    const backend = process.argv[2] as WorkerBackend;
+    const modulePath = `./${backend}${path.extname(__filename)}`;
+    const module = await import(modulePath);
+    const llm: ILLM<unknown, any, any, any, any> = new module[
+        backendExports[backend]
+    ]();
+    const controllers = new Map<number, AbortController>();
+    const flows = new Map<number, StreamFlow>();
+
+    process.on("message", (request: WorkerRequest) => {
+        if (request.type === "abort") {
+            controllers.get(request.id)?.abort();
+            flows.get(request.id)?.wake?.();
+            return;
+        }
+        if (request.type === "ack") {
+            const flow = flows.get(request.id);
+            if (flow) {
+                flow.inFlight--;
+                flow.wake?.();
+            }
+            return;
+        }
+
+        const controller = new AbortController();
+        const flow: StreamFlow = { inFlight: 0 };
+        controllers.set(request.id, controller);
+        flows.set(request.id, flow);
+
+        handle(llm, request, controller.signal, flow)
+            .then(
+                (value) => send({ id: request.id, type: "result", value }),
+                (error) =>
+                    send({
+                        id: request.id,
+                        type: "error",
+                        error: serializeError(error),
+                    })
+            )
+            .then(() => {
+                controllers.delete(request.id);
+                flows.delete(request.id);
+            });
+    });
+
+    // Nothing is left to serve once the host is gone.
+    process.on("disconnect", () => process.exit(0));
+
+    send({ id: 0, type: "ready" });
+};
+
+if (process.send) {
+    main().catch((error) => {
+        console.error(error);
+        process.exit(1);
+    });
+}
@@ -0,0 +1,395 @@
+import { fork, type ChildProcess } from "child_process";
+import path from "path";
+
+import { toLLMError, WorkerCrashedError } from "./error";
+import {
+    type CompletionCallback,
+    type EmbeddingProgressCallback,
+    type ILLM,
+    type LLMResult,
+    type LLMStreamResult,
+} from "./type";
+import type { LLamaCpp } from "./llama-cpp";
+import type { LLMRS } from "./llm-rs";
+import type { RwkvCpp } from "./rwkv-cpp";
+
+interface WorkerBackends {
+    "llama-cpp": LLamaCpp;
+    "rwkv-cpp": RwkvCpp;
+    "llm-rs": LLMRS;
+}
+
+export type WorkerBackend = keyof WorkerBackends;
+
+type LoadConfigOf<B extends WorkerBackend> = Parameters<
+    WorkerBackends[B]["load"]
+>[0];
+type InferenceArgumentsOf<B extends WorkerBackend> = Parameters<
+    WorkerBackends[B]["createCompletion"]
+>[0];
+type EmbeddingArgumentsOf<B extends WorkerBackend> = Parameters<
+    WorkerBackends[B]["getEmbedding"]
+>[0];
+type TokenizeArgumentsOf<B extends WorkerBackend> = Parameters<
+    WorkerBackends[B]["tokenize"]
+>[0];
+
+type WorkerMethod =
+    | "load"
+    | "createCompletion"
+    | "createCompletionStream"
+    | "getEmbedding"
+    | "getDefaultEmbedding"
+    | "getEmbeddings"
+    | "tokenize"
+    | "dispose";
+
+export type WorkerRequest =
+    | { id: number; type: "call"; method: WorkerMethod; args: unknown[] }
+    | { id: number; type: "abort" }
+    // A stream result was read, the worker sends one more.
+    | { id: number; type: "ack" };
+
+export interface SerializedError {
+    message: string;
+}
+
+export type WorkerResponse =
+    | { id: number; type: "ready" }
+    | { id: number; type: "result"; value: unknown }
+    | { id: number; type: "callback"; value: unknown }
+    | { id: number; type: "error"; error: SerializedError };
+
+export interface WorkerOptions {
+    /** Restart the worker and reload the model after a crash, default true */
+    autoRestart?: boolean;
+    /** Extra arguments for the node process running the worker */
+    execArgv?: string[];
+}
+
+interface PendingCall {
+    child: ChildProcess;
+    resolve: (value: any) => void;
+    reject: (error: unknown) => void;
+    onCallback?: (value: any, ack: () => void) => void;
+}
+
+const deserializeError = (error: SerializedError) =>
+    toLLMError(new Error(error.message));
+
+/**
+ * Runs a backend in a child process, so a native crash only takes down the
+ * worker. The worker is restarted and the model loaded again after a crash,
+ * the calls that were running reject with `WorkerCrashedError`.
+ */
+export class LLMWorker<B extends WorkerBackend>
+    implements
+        ILLM<
+            ChildProcess | undefined,
+            LoadConfigOf<B>,
+            InferenceArgumentsOf<B>,
+            EmbeddingArgumentsOf<B>,
+            TokenizeArgumentsOf<B>
+        >
+{
+    instance: ChildProcess | undefined;
+
+    private ready: Promise<void> | undefined;
+    private loadConfig: LoadConfigOf<B> | undefined;
+    private disposed = false;
+    private nextId = 1;
+    private pending = new Map<number, PendingCall>();
+
+    constructor(
+        private readonly backend: B,
+        private readonly options: WorkerOptions = {}
+    ) {}
+
+    private spawn() {
+        const ext = path.extname(__filename);
+        const child = fork(
+            path.join(__dirname, `worker-process${ext}`),
+            [this.backend],
+            {
+                serialization: "advanced",
+                execArgv: this.options.execArgv ?? process.execArgv,
+            }
+        );
+        this.instance = child;
+
+        this.ready = new Promise<void>((resolve, reject) => {
+            child.once("message", () => resolve());
+            child.once("exit", () =>
+                reject(new WorkerCrashedError("The worker failed to start"))
+            );
+        });
+        // The calls waiting on `ready` report the failure.
+        this.ready.catch(() => undefined);
+
+        // Sending to a dead worker emits an error, the exit handler handles it.
+        child.on("error", () => undefined);
+
+        child.on("message", (response: WorkerResponse) => {
+            const call = this.pending.get(response.id);
+            switch (response.type) {
+                case "callback":
+                    call?.onCallback?.(response.value, () =>
+                        child.send({ id: response.id, type: "ack" })
+                    );
+                    break;
+                case "result":
+                    this.pending.delete(response.id);
+                    call?.resolve(response.value);
+                    break;
+                case "error":
+                    this.pending.delete(response.id);
+                    call?.reject(deserializeError(response.error));
+                    break;
+            }
+        });
+
+        child.on("exit", (code, signal) => {
+            const reason = signal ? `signal ${signal}` : `code ${code}`;
+            for (const [id, call] of this.pending) {
+                if (call.child === child) {
+                    this.pending.delete(id);
+                    call.reject(
+                        new WorkerCrashedError(
+                            `The worker exited with ${reason}`
+                        )
+                    );
+                }
+            }
+
+            if (this.instance !== child) {
+                return;
+            }
+            this.instance = undefined;
+
+            if (
+                !this.disposed &&
+                this.loadConfig !== undefined &&
+                (this.options.autoRestart ?? true)
+            ) {
+                this.restart(this.loadConfig);
+            } else {
+                this.ready = undefined;
+            }
+        });
+    }
+
+    private restart(config: LoadConfigOf<B>) {
+        // A worker that crashes again while reloading is not restarted.
+        this.loadConfig = undefined;
+        this.spawn();
+        this.ready = this.ready!.then(() => this.send("load", [config])).then(
+            () => {
+                this.loadConfig = config;
+            }
+        );
+        this.ready.catch(() => undefined);
+    }
+
+    private send(
+        method: WorkerMethod,
+        args: unknown[],
+        abortSignal?: AbortSignal,
+        onCallback?: (value: any, ack: () => void) => void
+    ): Promise<any> {
+        const child = this.instance;
+        if (!child) {
+            return Promise.reject(
+                new WorkerCrashedError("The worker is not running")
+            );
+        }
+
+        const id = this.nextId++;
+        const abort = () => child.send({ id, type: "abort" });
+
+        return new Promise((resolve, reject) => {
+            const done = () => abortSignal?.removeEventListener("abort", abort);
+            this.pending.set(id, {
+                child,
+                resolve: (value) => {
+                    done();
+                    resolve(value);
+                },
+                reject: (error) => {
+                    done();
+                    reject(error);
+                },
+                onCallback,
+            });
+            abortSignal?.addEventListener("abort", abort);
+            child.send({ id, type: "call", method, args });
+            if (abortSignal?.aborted) {
+                abort();
+            }
+        });
+    }
+
+    private async call(
+        method: WorkerMethod,
+        args: unknown[],
+        abortSignal?: AbortSignal,
+        onCallback?: (value: any, ack: () => void) => void
+    ): Promise<any> {
+        while (true) {
+            const ready = this.ready;
+            if (!ready) {
+                throw new WorkerCrashedError("The worker is not running");
+            }
+            await ready;
+            // A worker restarted in the meantime has to reload the model first.
+            if (this.ready === ready) {
+                return this.send(method, args, abortSignal, onCallback);
+            }
+        }
+    }
+
+    async load(config: LoadConfigOf<B>, abortSignal?: AbortSignal) {
+        if (!this.instance) {
+            this.disposed = false;
+            this.spawn();
+        }
+        await this.call("load", [config], abortSignal);
+        this.loadConfig = config;
+    }
+
+    createCompletion(
+        params: InferenceArgumentsOf<B>,
+        callback: CompletionCallback,
+        abortSignal?: AbortSignal
+    ): Promise<LLMResult> {
+        return this.call("createCompletion", [params], abortSignal, (data) =>
+            callback(data)
+        );
+    }
+
+    async *createCompletionStream(
+        params: InferenceArgumentsOf<B>,
+        abortSignal?: AbortSignal,
+        bufferSize?: number
+    ): AsyncGenerator<LLMStreamResult, void, undefined> {
+        // Stops the worker side when the loop exits early.
+        const controller = new AbortController();
+        const abort = () => controller.abort();
+        abortSignal?.addEventListener("abort", abort);
+
+        // The worker stops sending once `bufferSize` results are not acked,
+        // so the queue stays bounded while the loop is slower than the model.
+        const queue: { result: LLMStreamResult; ack: () => void }[] = [];
+        let notify: (() => void) | undefined;
+        let finished = false;
+        let error: unknown;
+
+        this.call(
+            "createCompletionStream",
+            [params, bufferSize],
+            controller.signal,
+            (result: LLMStreamResult, ack) => {
+                queue.push({ result, ack });
+                notify?.();
+            }
+        ).then(
+            () => {
+                finished = true;
+                notify?.();
+            },
+            (e) => {
+                error = e;
+                finished = true;
+                notify?.();
+            }
+        );
+
+        try {
+            while (true) {
+                if (queue.length) {
+                    const { result, ack } = queue.shift()!;
+                    yield result;
+                    ack();
+                } else if (finished) {
+                    if (error) {
+                        throw error;
+                    }
+                    return;
+                } else {
+                    await new Promise<void>((resolve) => (notify = resolve));
+                    notify = undefined;
+                }
+            }
+        } finally {
+            abortSignal?.removeEventListener("abort", abort);
+            if (!finished) {
+                controller.abort();
+            }
+        }
+    }
+
+    getEmbedding(
+        params: EmbeddingArgumentsOf<B>,
+        abortSignal?: AbortSignal
+    ): Promise<number[]> {
+        return this.call("getEmbedding", [params], abortSignal);
+    }
+
+    getDefaultEmbedding(
+        text: string,
+        abortSignal?: AbortSignal
+    ): Promise<number[]> {
+        return this.call("getDefaultEmbedding", [text], abortSignal);
+    }
+
+    getEmbeddings(
+        texts: string[],
+        onProgress?: EmbeddingProgressCallback,
+        abortSignal?: AbortSignal
+    ): Promise<number[][]> {
+        return this.call(
+            "getEmbeddings",
+            [texts],
+            abortSignal,
+            onProgress && ((progress) => onProgress(progress))
+        );
+    }
+
+    tokenize(
+        content: TokenizeArgumentsOf<B>,
+        abortSignal?: AbortSignal
+    ): Promise<number[]> {
+        return this.call("tokenize", [content], abortSignal);
+    }
+
+    /** Frees the model and stops the worker, `load` starts a new one. */
+    async dispose(): Promise<void> {
+        const child = this.instance;
+        this.disposed = true;
+        this.loadConfig = undefined;
+        if (!child) {
+            return;
+        }
+
+        try {
+            await this.call("dispose", []);
+        } finally {
+            this.instance = undefined;
+            this.ready = undefined;
+            child.disconnect();
+        }
+    }
+}
+
+/**
+ * Returns a backend class for `new LLM(...)` that runs `backend` in a worker
+ * process, e.g. `new LLM(createWorkerBackend("llama-cpp"))`.
+ */
+export const createWorkerBackend = <B extends WorkerBackend>(
+    backend: B,
+    options?: WorkerOptions
+) =>
+    class extends LLMWorker<B> {
+        constructor() {
+            super(backend, options);
+        }
+    };